@@ -2,7 +2,38 @@ from .shazamio_core import (
     Geolocation,
     SignatureSong,
     Signature,
+    QualityReport,
     SignatureError,
+    FFmpegNotFoundError,
+    FFprobeNotFoundError,
+    UnsupportedCodecError,
+    FFmpegTimeoutError,
+    FFmpegOutputTooLargeError,
+    FFmpegError,
     Recognizer,
     SearchParams,
+    FingerprintIndex,
+    FingerprintDatabase,
+    DuplicateGroup,
+    DuplicateReport,
+    Alignment,
+    IndexMatch,
+    MediaInfo,
+    StreamSignature,
+    MatchResponse,
+    TrackMatch,
+    Track,
+    TrackImages,
+    Hub,
+    HubAction,
+    Section,
 )
+
+# Only built with the `client` feature
+try:
+    from .shazamio_core import (
+        RecognitionClient,
+        RecognitionError,
+    )
+except ImportError:
+    pass
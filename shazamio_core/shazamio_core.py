@@ -1,28 +1,161 @@
 from dataclasses import dataclass
-from typing import Union, Optional
+from typing import Literal, Union, Optional
 from os import PathLike
+from mmap import mmap
+
+# Objects supporting the buffer protocol, read without being copied when they are bytes
+BytesLike = Union[bytes, bytearray, memoryview, mmap]
 
 
 @dataclass
 class Geolocation:
-    altitude: int
-    latitude: int
-    longitude: int
+    """
+    Position sent along with a signature; recognition results depend on the region.
+
+    **altitude**: Meters above sea level.
+    **latitude**: Degrees from -90 to 90, positive to the north.
+    **longitude**: Degrees from -180 to 180, positive to the east.
+
+    Raises `ValueError` when the coordinates are out of range.
+    """
+    altitude: float
+    latitude: float
+    longitude: float
 
 
 @dataclass
 class SignatureSong:
+    """
+    **samples**: Duration of the fingerprinted audio, in milliseconds.
+    **timestamp**: Time the signature was made, in milliseconds since the Unix epoch.
+    **samples_used**: Number of 16 kHz samples fingerprinted. When not given to the constructor,
+        all the samples of the `samples` milliseconds.
+    """
     samples: int
     timestamp: int
     uri: str
+    samples_used: int
+
+    def __init__(self, samples: int, timestamp: int, uri: str, samples_used: Optional[int] = None) -> None:
+        raise NotImplemented
+
+
+@dataclass
+class QualityReport:
+    """
+    Pre-flight report telling whether a signature is likely to be recognized.
+
+    **peaks_per_second**: Peaks found per second in each frequency band, keyed by band
+        (`"250_520"`, `"520_1450"`, `"1450_3500"`, `"3500_5500"`, in Hz).
+    **rms_dbfs** / **peak_dbfs**: RMS and peak level of the analysed segment, in dB relative to full scale.
+    **clipped_ratio**: Share of samples at full scale (0 to 1), as decoded before downmixing and resampling.
+    **silence_ratio**: Share of the segment quieter than -60 dBFS (0 to 1).
+    **effective_bandwidth_hz**: Highest frequency holding signal within 50 dB of the strongest one.
+    **likely_unrecognizable**: True when any of `reasons` applies.
+    """
+    duration_seconds: float
+    peaks_per_second: dict[str, float]
+    rms_dbfs: float
+    peak_dbfs: float
+    clipped_ratio: float
+    silence_ratio: float
+    effective_bandwidth_hz: float
+    likely_unrecognizable: bool
+    reasons: list[str]
 
 
 @dataclass
 class Signature:
+    """
+    **timestamp**: Time the signature was made, in milliseconds since the Unix epoch.
+    """
     geolocation: Geolocation
     signature: SignatureSong
     timestamp: int
     timezone: str
+    quality: Optional[QualityReport]
+
+    def to_request_json(
+            self,
+            language: str = "en-US",
+            country: str = "GB",
+            device: str = "web",
+            tag_id: Optional[str] = None,
+            installation_id: Optional[str] = None,
+    ) -> str:
+        """
+        Serialize the discovery request recognizing this signature, as `{"path": ..., "body": ...}`.
+
+        The body holds the geolocation, the signature (uri, samplems, timestamp), the timestamp,
+        the timezone and an empty context, always serialized in the same order. The path is relative
+        to the base URL of the endpoint, e.g. `https://amp.shazam.com`.
+
+        :param language: Language of the results, such as `en-US`.
+        :param country: Two-letter code of the country whose catalogue is searched.
+        :param device: `iphone`, `android` or `web`.
+        :param tag_id: Identifier of the tag in the path. **Default:** a random upper case UUID.
+        :param installation_id: Identifier of the installation in the path. **Default:** a random upper case UUID.
+        :raises ValueError: if a part of the path holds characters other than letters, digits and dashes.
+        """
+        raise NotImplemented
+
+
+@dataclass
+class StreamSignature:
+    """
+    Signature of one audio stream of a media file holding several of them.
+
+    **stream_index**: Position among the audio streams of the file, from 0.
+    **signature**: None when the stream could not be fingerprinted.
+    **language**: Language tag of the stream, such as `eng`, or None when it is not tagged.
+    **error**: Why the stream could not be fingerprinted, None when it was.
+    """
+    stream_index: int
+    signature: Optional[Signature]
+    language: Optional[str]
+    error: Optional[str] = None
+
+
+@dataclass
+class DuplicateGroup:
+    paths: list[str]
+    similarity: float
+
+
+@dataclass
+class DuplicateReport:
+    """
+    Outcome of duplicate detection over a set of files.
+
+    **failed**: Error message by path, for the files which could not be decoded and were left out of the groups.
+    """
+    groups: list[DuplicateGroup]
+    failed: dict[str, str]
+
+
+@dataclass
+class Alignment:
+    offset_ms: float
+    confidence: float
+    votes: int
+    drift_ppm: Optional[float]
+
+
+@dataclass
+class MediaInfo:
+    """
+    Description of a media file. Fields which could not be determined are None.
+
+    **bitrate**: In bits per second, estimated from the size and the duration when the container does not tell.
+    **tags**: ID3, Vorbis comment or MP4 tags, with lowercase keys such as `title`, `artist` or `album`.
+    """
+    duration_seconds: Optional[float]
+    container: Optional[str]
+    codec: Optional[str]
+    sample_rate: Optional[int]
+    channels: Optional[int]
+    bitrate: Optional[int]
+    tags: dict[str, str]
 
 
 @dataclass(frozen=True)
@@ -38,8 +171,145 @@ class SearchParams:
           - Example: If the audio is **8 seconds** and `segment_duration_seconds = 10`, the entire **8-second file** will be processed.
         - **Audio is always converted to mono and down sampled to 16 kHz** before analysis.
         - This parameter determines the number of samples used for frequency analysis and fingerprint generation.
+        - When the duration of the file is known, the decoder seeks to the segment and only decodes it,
+          so long files are not decoded in full.
+
+    **pad_last_frame**: Whether to complete the trailing partial frame with silence.
+        - **Default:** False.
+        - Audio is analysed in frames of 128 samples; by default the samples left after the last
+          full frame are dropped. When enabled, they are padded with zeroes and used as well.
+        - The number of samples actually analysed is reported in `SignatureSong.samples_used`.
+        - Segments shorter than ~0.37 seconds cannot be fingerprinted and raise `SignatureError`.
+
+    **audio_stream_index**: Position of the audio stream to fingerprint, among the audio streams only, from 0.
+        - **Default:** None, the default stream chosen by the decoder.
+        - Useful for videos with several audio tracks (dubbed languages, commentary).
+        - Streams other than the first need the `symphonia` feature or FFmpeg.
+
+    **audio_language**: Language tag of the audio stream to fingerprint, such as `eng`, compared case-insensitively.
+        - **Default:** None.
+        - The first stream in that language is used.
+        - Cannot be combined with `audio_stream_index`: setting both raises `ValueError`.
+        - Raises `SignatureError` when no audio stream is tagged with that language.
+
+    **geolocation**: Position sent along with the signature.
+        - **Default:** None, the geolocation of the `Recognizer`.
+
+    **timezone**: IANA name of the timezone sent along with the signature, such as `America/New_York`.
+        - **Default:** None, the timezone of the `Recognizer`.
+
+    **timestamp_ms**: Timestamp of the signature, in milliseconds since the Unix epoch.
+        - **Default:** None, the clock of the `Recognizer`.
+        - Setting it makes the output reproducible, e.g. in tests.
     """
     segment_duration_seconds: int = 10
+    pad_last_frame: bool = False
+    audio_stream_index: Optional[int] = None
+    audio_language: Optional[str] = None
+    geolocation: Optional[Geolocation] = None
+    timezone: Optional[str] = None
+    timestamp_ms: Optional[int] = None
+
+
+@dataclass
+class TrackMatch:
+    """
+    Position of the signature within a matching recording.
+
+    **offset**: Seconds from the start of the recording.
+    **extra**: Fields of the answer which are not modelled, as received.
+    """
+    id: str
+    offset: float
+    timeskew: float
+    frequencyskew: float
+    extra: dict
+
+
+@dataclass
+class TrackImages:
+    """URLs of the artwork of a track."""
+    background: Optional[str]
+    coverart: Optional[str]
+    coverarthq: Optional[str]
+    extra: dict
+
+
+@dataclass
+class HubAction:
+    name: Optional[str]
+    type: Optional[str]
+    id: Optional[str]
+    uri: Optional[str]
+    extra: dict
+
+
+@dataclass
+class Hub:
+    """Where a track can be played, such as a streaming service."""
+    type: Optional[str]
+    displayname: Optional[str]
+    image: Optional[str]
+    explicit: Optional[bool]
+    actions: list[HubAction]
+    extra: dict
+
+
+@dataclass
+class Section:
+    """A tab of the track page, such as `SONG`, `LYRICS` or `ARTIST`."""
+    type: str
+    tabname: Optional[str]
+    extra: dict
+
+
+@dataclass
+class Track:
+    """
+    **subtitle**: Usually the artist.
+    """
+    key: str
+    title: str
+    subtitle: Optional[str]
+    isrc: Optional[str]
+    url: Optional[str]
+    images: Optional[TrackImages]
+    hub: Optional[Hub]
+    sections: list[Section]
+    extra: dict
+
+
+@dataclass
+class MatchResponse:
+    """
+    Answer of the discovery endpoint to a recognition request.
+
+    Every model keeps the fields it does not know in `extra`, so nothing of the answer is lost.
+
+    **track**: Best matching track, None when nothing matched.
+    **retryms**: Milliseconds to wait before recognizing more audio of the same recording,
+        sent when nothing matched.
+    """
+    matches: list[TrackMatch]
+    track: Optional[Track]
+    tagid: Optional[str]
+    timestamp: Optional[int]
+    timezone: Optional[str]
+    retryms: Optional[int]
+    extra: dict
+
+    @staticmethod
+    def from_json(json: str) -> "MatchResponse":
+        """
+        Parse the JSON answer of the discovery endpoint.
+
+        :raises ValueError: if it is not valid JSON or does not have the expected shape.
+        """
+        raise NotImplemented
+
+    def to_json(self) -> str:
+        """Serialize the answer back, with the fields which are not modelled."""
+        raise NotImplemented
 
 
 class SignatureError(Exception):
@@ -53,6 +323,62 @@ class SignatureError(Exception):
         return f"SignatureError({self.message})"
 
 
+class FFmpegNotFoundError(SignatureError):
+    """
+    Raised when FFmpeg cannot be found or executed, by `Recognizer(require_ffmpeg=True)`
+    or when decoding an input no other decoder supports.
+    """
+
+
+class FFprobeNotFoundError(SignatureError):
+    """
+    Raised when FFprobe cannot be found or executed while selecting an audio stream by language
+    with FFmpeg. Probing media info falls back to the other decoders instead.
+    """
+
+
+class UnsupportedCodecError(SignatureError):
+    """
+    Raised when the input uses a codec which only FFmpeg decodes while it cannot be executed.
+    Opus audio, in Ogg or in MKV/WebM, is not supported without FFmpeg, even with the `symphonia` feature.
+    """
+
+
+class FFmpegTimeoutError(SignatureError):
+    """
+    Raised when FFmpeg runs for longer than the `ffmpeg_timeout_seconds` of the `Recognizer`, or produces
+    no output for its `ffmpeg_inactivity_timeout_seconds`; it is killed.
+    """
+
+
+class FFmpegOutputTooLargeError(SignatureError):
+    """Raised when FFmpeg outputs more than the `ffmpeg_max_output_bytes` of the `Recognizer`; it is killed."""
+
+
+class FFmpegError(SignatureError):
+    """
+    Raised when FFmpeg (or FFprobe) fails to read or convert a file.
+
+    **command_line**: The command which was run.
+    **stderr**: What FFmpeg printed about the failure (the end of it, if it was long).
+    **exit_code**: Exit code of FFmpeg, None if it was terminated by a signal.
+    """
+    command_line: str
+    stderr: str
+    exit_code: Optional[int]
+
+
+class RecognitionError(Exception):
+    """
+    Raised by `RecognitionClient.recognize` when the request fails.
+
+    **status**: HTTP status of the answer when the endpoint rejected the request, else None.
+    **body**: Body of that answer, else None.
+    """
+    status: Optional[int]
+    body: Optional[str]
+
+
 class Recognizer:
     """
     Recognizer uses a Rust implementation under the hood.
@@ -61,7 +387,22 @@ class Recognizer:
     processing logic is implemented in Rust and accessed via FFI.
     """
 
-    def __init__(self, segment_duration_seconds: int = 10) -> None:
+    def __init__(
+            self,
+            segment_duration_seconds: int = 10,
+            ffmpeg_path: Optional[Union[str, PathLike]] = None,
+            ffprobe_path: Optional[Union[str, PathLike]] = None,
+            require_ffmpeg: bool = False,
+            ffmpeg_timeout_seconds: float = 120.0,
+            ffmpeg_inactivity_timeout_seconds: float = 0.0,
+            ffmpeg_max_output_bytes: int = 512 * 1024 * 1024,
+            ffmpeg_temporary_files: bool = False,
+            decoder_order: Optional[list[str]] = None,
+            geolocation: Optional[Geolocation] = None,
+            timezone: Optional[str] = None,
+            use_system_timezone: bool = False,
+            timestamp_ms: Optional[int] = None,
+    ) -> None:
         """
         :param segment_duration_seconds: The duration (in seconds) of the audio segment to analyze.
             - **Default:** 12 seconds.
@@ -71,10 +412,75 @@ class Recognizer:
               - Example: If the audio is **8 seconds** and `segment_duration_seconds = 10`, the entire **8-second file** will be processed.
             - **Audio is always converted to mono and down sampled to 16 kHz** before analysis.
             - This parameter determines the number of samples used for frequency analysis and fingerprint generation.
+        :param ffmpeg_path: Path of the FFmpeg executable used for the formats which cannot be decoded natively.
+            - **Default:** the `SHAZAMIO_FFMPEG` environment variable if set, otherwise `ffmpeg` is searched
+              for once (on the `PATH` and next to the executable) and the result is cached.
+        :param ffprobe_path: Path of the FFprobe executable, used to select audio streams by language.
+            - **Default:** the `SHAZAMIO_FFPROBE` environment variable if set, otherwise `ffprobe` in the
+              directory of `ffmpeg_path` when it has one, otherwise `ffprobe` is searched for once like FFmpeg.
+        :param require_ffmpeg: Check that FFmpeg can be executed now instead of when a file needs it.
+        :param ffmpeg_timeout_seconds: Time after which FFmpeg is killed, counted from its start to its exit,
+            whether it is still converting or its output is still being fingerprinted. 0 disables the timeout.
+        :param ffmpeg_inactivity_timeout_seconds: FFmpeg is also killed when it is waited for this long without
+            producing any output. The time spent fingerprinting its output does not count. 0, the default,
+            disables this timeout.
+        :param ffmpeg_max_output_bytes: Size of decoded audio (16 kHz mono 16-bit PCM, 32 kB per second)
+            after which FFmpeg is killed. The default allows a bit more than 4 hours. 0 disables the limit.
+            FFmpeg is also killed when the awaiting task is cancelled.
+        :param ffmpeg_temporary_files: In-memory input never touches the disk by default. FFmpeg cannot read
+            MP4, M4A and MOV input whose `moov` atom comes after the media data from a pipe, so such input is
+            decoded with Symphonia when the feature is enabled, and raises `SignatureError` when it cannot be.
+            Setting this writes it to a temporary file for FFmpeg instead, removed once decoded.
+        :param decoder_order: Names of the decoders to try first, in that order; the others are tried after them.
+            - **Default:** `rodio`, then `symphonia` when the feature is enabled, then `ffmpeg`.
+            - The format of each input is recognized from its first bytes, and only the decoders
+              supporting it are tried. The first of them decodes the input; the next one is only
+              tried when it fails to open it (e.g. an unsupported codec), and the error of the last
+              decoder is raised when they all fail. FFmpeg timeouts and output limits are raised
+              right away.
+            - Opus, in Ogg or in MKV/WebM, is only decoded by `ffmpeg`: it is not supported without it,
+              and decoding raises `UnsupportedCodecError`.
+        :param geolocation: Position sent along with the signatures, unless overridden by `SearchParams`.
+            - **Default:** altitude 300 m, latitude 45, longitude 2 (central France).
+        :param timezone: IANA name of the timezone sent along with the signatures, unless overridden by `SearchParams`.
+            - **Default:** `Europe/Paris`, or the timezone of the system with `use_system_timezone`.
+        :param use_system_timezone: Send the timezone of the system, read from the `TZ` environment variable,
+            `/etc/timezone` or `/etc/localtime`, when `timezone` is not given. Falls back to `Europe/Paris`
+            (a warning is logged) when it cannot be determined.
+        :param timestamp_ms: Fixed timestamp given to every signature, in milliseconds since the Unix epoch,
+            unless overridden by `SearchParams`. Makes the output reproducible, e.g. in tests.
+            - **Default:** None, the current time.
+        :raises FFmpegNotFoundError: if `require_ffmpeg` is set and FFmpeg cannot be executed.
+        :raises SignatureError: if `decoder_order` names an unknown decoder.
         """
         self.segment_duration_seconds = segment_duration_seconds
         raise NotImplemented
 
+    @property
+    def ffmpeg_path(self) -> Optional[str]:
+        """Path of the FFmpeg executable used as a decoding fallback, None if it was not found."""
+        raise NotImplemented
+
+    @property
+    def ffprobe_path(self) -> Optional[str]:
+        """Path of the FFprobe executable used to select audio streams by language, None if it was not found."""
+        raise NotImplemented
+
+    @property
+    def decoders(self) -> list[str]:
+        """Names of the decoders, in the order they are tried."""
+        raise NotImplemented
+
+    @property
+    def geolocation(self) -> Geolocation:
+        """Position sent along with the signatures, unless overridden by `SearchParams`."""
+        raise NotImplemented
+
+    @property
+    def timezone(self) -> str:
+        """Timezone sent along with the signatures, unless overridden by `SearchParams`."""
+        raise NotImplemented
+
     async def recognize_path(
             self,
             value: Union[str, PathLike],
@@ -88,13 +494,17 @@ class Recognizer:
         :param value: Path to an audio file.
         :param options: Search parameters.
         :return: Signature object.
+        :raises ValueError: if the search parameters select a stream both by index and by language.
         :raises SignatureError: if an error occurs.
+        :raises FFmpegTimeoutError: if FFmpeg exceeds its timeout.
+        :raises FFmpegOutputTooLargeError: if FFmpeg exceeds its output limit.
+        :raises FFmpegError: if FFmpeg fails to convert the audio.
         """
         raise NotImplemented
 
     async def recognize_bytes(
             self,
-            value: bytes,
+            value: BytesLike,
             options: Optional[SearchParams] = None,
     ) -> Signature:
         """
@@ -102,9 +512,286 @@ class Recognizer:
 
         This method is a Python wrapper around a Rust implementation.
 
-        :param value: Raw audio file as bytes.
+        :param value: Raw audio file as bytes, or any object supporting the buffer protocol
+            (bytearray, memoryview, mmap). `bytes`, and memoryviews of them, are read without being
+            copied; any other object is copied first, even when it is read-only, as it could still
+            be modified while it is decoded.
         :param options: Search parameters.
         :return: Signature object.
+        :raises ValueError: if the search parameters select a stream both by index and by language.
         :raises SignatureError: if an error occurs.
+        :raises FFmpegTimeoutError: if FFmpeg exceeds its timeout.
+        :raises FFmpegOutputTooLargeError: if FFmpeg exceeds its output limit.
+        :raises FFmpegError: if FFmpeg fails to convert the audio.
+        """
+        raise NotImplemented
+
+    async def recognize_pcm(
+            self,
+            samples: Union[bytes, bytearray, memoryview],
+            sample_rate: int,
+            channels: int = 1,
+            sample_format: Literal["i16", "i32", "f32"] = "i16",
+            options: Optional[SearchParams] = None,
+    ) -> Signature:
+        """
+        Recognize audio already decoded by your own pipeline, without going through a decoder or FFmpeg.
+
+        The samples are downmixed and resampled to 16 kHz mono internally, and the segment
+        selected by the search parameters is fingerprinted.
+
+        :param samples: Interleaved samples, from any object supporting the buffer protocol:
+            an `array.array` or NumPy array of the matching type, or raw bytes in native byte order.
+        :param sample_rate: Sample rate of the audio in Hz.
+        :param channels: Number of interleaved channels.
+        :param sample_format: `i16` or `i32` for signed integers, `f32` for floats between -1.0 and 1.0.
+        :param options: Search parameters.
+        :return: Signature object.
+        :raises ValueError: if the search parameters select a stream both by index and by language.
+        :raises SignatureError: if the samples do not match the format, or are too short.
+        """
+        raise NotImplemented
+
+    async def find_duplicates(
+            self,
+            paths: list[Union[str, PathLike]],
+            min_similarity: float = 0.1,
+    ) -> DuplicateReport:
+        """
+        Group files holding the same recording, such as re-encodes at different bitrates or formats.
+
+        Every file is fingerprinted over its full length, while it is decoded so that memory use
+        does not grow with its duration, and two files are linked when the share of their landmarks
+        agreeing on a single time offset reaches `min_similarity`.
+        Files which cannot be decoded are skipped and reported in `failed` with their error.
+
+        :param paths: Paths of the audio files to compare.
+        :param min_similarity: Minimum similarity (0 to 1) for two files to be grouped.
+        :return: Groups of at least two files, with the weakest similarity which linked each group,
+            and the files which could not be decoded.
+        """
+        raise NotImplemented
+
+    async def align(
+            self,
+            reference: Union[str, PathLike],
+            other: Union[str, PathLike],
+            estimate_drift: bool = False,
+    ) -> Optional[Alignment]:
+        """
+        Find the time offset between two recordings of the same event, e.g. to sync multi-camera footage.
+
+        Both files are fingerprinted over their full length and their peaks are cross-matched.
+
+        :param reference: Path of the reference recording.
+        :param other: Path of the recording to align on the reference.
+        :param estimate_drift: Also measure the offset over 30 second windows and return the clock
+            drift of `other` relative to `reference`, in parts per million. Needs recordings sharing
+            at least a minute of audio.
+        :return: `offset_ms` is the position in `reference` where `other` starts (negative when `other`
+            starts first), `confidence` the share of peaks agreeing on it (0 to 1).
+            None if the recordings share no audio.
+        :raises SignatureError: if a file cannot be decoded.
+        """
+        raise NotImplemented
+
+
+    async def recognize_streams(
+            self,
+            value: Union[str, PathLike, BytesLike],
+            options: Optional[SearchParams] = None,
+    ) -> list[StreamSignature]:
+        """
+        Recognize every audio stream of a media file, e.g. each dubbed language of a video.
+
+        The streams are listed with FFprobe when available, otherwise with the `symphonia` feature.
+        Without either, the streams of a container cannot be listed: only the first one is
+        fingerprinted, and a warning is logged.
+        The stream selected in the search parameters is ignored.
+
+        :param value: Path to a media file, or its content as a bytes-like object.
+        :param options: Search parameters.
+        :return: One StreamSignature per audio stream, in stream order. Streams which cannot be
+            fingerprinted have no signature, and the reason in `error`.
+        :raises ValueError: if the search parameters select a stream both by index and by language.
+        :raises SignatureError: if none of the streams can be fingerprinted.
+        :raises FFmpegTimeoutError: if FFmpeg exceeds its timeout.
+        :raises FFmpegOutputTooLargeError: if FFmpeg exceeds its output limit.
+        :raises FFmpegError: if FFmpeg fails to convert the audio.
+        """
+        raise NotImplemented
+
+    async def probe(self, value: Union[str, PathLike, BytesLike]) -> MediaInfo:
+        """
+        Describe a media file without fingerprinting it, e.g. to choose segment offsets or attach metadata.
+
+        FFprobe (installed next to FFmpeg) is used when available. Otherwise the file is opened with the
+        native decoders, which only report the duration, the sample rate, the channels and, with the
+        `symphonia` feature, the codec and tags.
+
+        :param value: Path to a media file, or its content as a bytes-like object.
+        :return: MediaInfo object.
+        :raises SignatureError: if the file cannot be read.
+        """
+        raise NotImplemented
+
+
+@dataclass
+class IndexMatch:
+    track_id: str
+    offset_seconds: float
+    votes: int
+
+
+class FingerprintIndex:
+    """
+    In-memory index of signatures from your own catalogue, allowing to recognize
+    audio locally without any remote service.
+
+    Signatures are split into landmark hashes (pairs of peaks of the same frequency band
+    with their frequency bins and time distance), stored in an inverted index by track ID.
+    To index whole tracks, generate their signatures with a `segment_duration_seconds`
+    larger than the track duration.
+    """
+
+    def __init__(self) -> None:
+        raise NotImplemented
+
+    def add(self, track_id: str, signature: Signature) -> None:
+        """
+        Index the peaks of a signature. Signatures added under the same ID are overlaid, each
+        starting at offset 0 of the track, e.g. several takes of the same recording.
+
+        :param track_id: Identifier of the track in your catalogue.
+        :param signature: Signature returned by `Recognizer`.
+        :raises SignatureError: if the signature cannot be decoded.
+        """
+        raise NotImplemented
+
+    def query(self, signature: Signature, min_votes: int = 1) -> Optional[IndexMatch]:
+        """
+        Find the indexed track sharing the most time-consistent landmarks with a signature.
+
+        :param signature: Signature of the audio to recognize.
+        :param min_votes: Minimum number of matching landmarks to report a match.
+        :return: The best track with the position of the query within it (in seconds)
+            and its vote count, or None if nothing matched.
+        :raises SignatureError: if the signature cannot be decoded.
+        """
+        raise NotImplemented
+
+    def __len__(self) -> int:
+        raise NotImplemented
+
+
+class FingerprintDatabase:
+    """
+    Fingerprint catalogue persisted on disk, with the same matching as `FingerprintIndex`.
+
+    The compacted postings are stored in a versioned file which is memory-mapped when opened,
+    so a large catalogue loads in milliseconds. New signatures are appended to a `<path>.log`
+    file next to it and merged into the main file by `compact()`.
+    """
+
+    def __init__(self, path: Union[str, PathLike]) -> None:
+        """
+        Open the database at `path`, creating it if it does not exist.
+
+        :raises SignatureError: if the file is not a valid database.
+        """
+        raise NotImplemented
+
+    def add(self, track_id: str, signature: Signature) -> None:
+        """
+        Durably append the peaks of a signature to the database log.
+
+        :param track_id: Identifier of the track in your catalogue.
+        :param signature: Signature returned by `Recognizer`.
+        :raises SignatureError: if the signature cannot be decoded or written.
+        """
+        raise NotImplemented
+
+    def query(self, signature: Signature, min_votes: int = 1) -> Optional[IndexMatch]:
+        """
+        Find the stored track sharing the most time-consistent landmarks with a signature.
+
+        :param signature: Signature of the audio to recognize.
+        :param min_votes: Minimum number of matching landmarks to report a match.
+        :return: The best match, or None if nothing matched.
+        :raises SignatureError: if the part of the file the signature looks up is corrupt.
+        """
+        raise NotImplemented
+
+    def compact(self) -> None:
+        """
+        Merge the log into a new database file, which atomically replaces the current one.
+        The database is left unchanged when the new file cannot be written.
+
+        :raises SignatureError: if the new file cannot be written, or the current one is corrupt.
+        """
+        raise NotImplemented
+
+    def __len__(self) -> int:
+        raise NotImplemented
+
+
+class RecognitionClient:
+    """
+    Async client submitting recognition requests to the discovery endpoint.
+
+    Only available when the module is built with the `client` feature. Each request POSTs the
+    body of `Signature.to_request_json` with a new tag identifier; the installation identifier
+    is drawn once per client. Connections are reused across requests.
+    """
+
+    def __init__(
+            self,
+            base_url: str = "https://amp.shazam.com",
+            user_agent: Optional[str] = None,
+            language: str = "en-US",
+            country: str = "GB",
+            device: str = "web",
+            timeout_seconds: Optional[float] = 20.0,
+    ) -> None:
+        """
+        :param base_url: Scheme and host of the endpoint, such as a local mock server or a proxy.
+        :param user_agent: User agent of the requests. **Default:** that of a desktop browser.
+        :param language: Language of the results, such as `en-US`.
+        :param country: Two-letter code of the country whose catalogue is searched.
+        :param device: `iphone`, `android` or `web`.
+        :param timeout_seconds: Requests taking longer are abandoned. Zero or less disables the timeout.
+        :raises ValueError: if the base URL is not an http or https URL, or if the language, country
+            or device holds characters other than letters, digits and dashes.
+        """
+        raise NotImplemented
+
+    @property
+    def base_url(self) -> str:
+        raise NotImplemented
+
+    @property
+    def user_agent(self) -> str:
+        raise NotImplemented
+
+    @property
+    def language(self) -> str:
+        raise NotImplemented
+
+    @property
+    def country(self) -> str:
+        raise NotImplemented
+
+    @property
+    def device(self) -> str:
+        raise NotImplemented
+
+    async def recognize(self, signature: Signature) -> MatchResponse:
+        """
+        Submit the request recognizing a signature.
+
+        :param signature: Signature returned by `Recognizer`.
+        :return: The answer of the endpoint.
+        :raises RecognitionError: if the request fails, the endpoint answers with an error status
+            or its answer does not have the expected shape.
         """
         raise NotImplemented
@@ -0,0 +1,98 @@
+use crate::errors::RecognitionError;
+use crate::fingerprinting::client::{self, ClientError, ClientSettings};
+use crate::fingerprinting::communication::RequestLocale;
+use crate::match_response::MatchResponse;
+use crate::response::Signature;
+use crate::utils::convert_signature_from_py;
+use pyo3::exceptions::PyValueError;
+use pyo3::{pyclass, pymethods, PyAny, PyErr, PyResult, Python};
+use std::time::Duration;
+
+fn to_recognition_error(error: ClientError) -> PyErr {
+    match &error {
+        ClientError::Status { status, body } => PyErr::new::<RecognitionError, _>((
+            error.to_string(),
+            Some(*status),
+            Some(body.clone()),
+        )),
+        _ => PyErr::new::<RecognitionError, _>((error.to_string(), None::<u16>, None::<String>)),
+    }
+}
+
+#[pyclass]
+pub(crate) struct RecognitionClient {
+    client: client::RecognitionClient,
+}
+
+#[pymethods]
+impl RecognitionClient {
+    #[new]
+    pub fn new(
+        base_url: Option<String>,
+        user_agent: Option<String>,
+        language: Option<String>,
+        country: Option<String>,
+        device: Option<String>,
+        timeout_seconds: Option<f64>,
+    ) -> PyResult<Self> {
+        let defaults = ClientSettings::default();
+        let default_locale = RequestLocale::default();
+
+        let settings = ClientSettings {
+            base_url: base_url.unwrap_or(defaults.base_url),
+            user_agent: user_agent.unwrap_or(defaults.user_agent),
+            locale: RequestLocale {
+                language: language.unwrap_or(default_locale.language),
+                country: country.unwrap_or(default_locale.country),
+                device: device.unwrap_or(default_locale.device),
+            },
+            timeout: match timeout_seconds {
+                Some(seconds) if seconds > 0.0 => Some(Duration::from_secs_f64(seconds)),
+                Some(_) => None,
+                None => defaults.timeout,
+            },
+        };
+
+        let client = client::RecognitionClient::new(settings)
+            .map_err(|e| PyValueError::new_err(e.to_string()))?;
+        Ok(RecognitionClient { client })
+    }
+
+    #[getter]
+    pub fn base_url(&self) -> String {
+        self.client.settings().base_url.clone()
+    }
+
+    #[getter]
+    pub fn user_agent(&self) -> String {
+        self.client.settings().user_agent.clone()
+    }
+
+    #[getter]
+    pub fn language(&self) -> String {
+        self.client.settings().locale.language.clone()
+    }
+
+    #[getter]
+    pub fn country(&self) -> String {
+        self.client.settings().locale.country.clone()
+    }
+
+    #[getter]
+    pub fn device(&self) -> String {
+        self.client.settings().locale.device.clone()
+    }
+
+    pub fn recognize<'p>(&self, py: Python<'p>, signature: &Signature) -> PyResult<&'p PyAny> {
+        let client = self.client.clone();
+        let signature = convert_signature_from_py(signature);
+
+        // The HTTP client runs on the Tokio runtime driving the future
+        pyo3_asyncio::tokio::future_into_py(py, async move {
+            client
+                .recognize::<MatchResponse>(&signature)
+                .await
+                .map_err(to_recognition_error)
+        })
+    }
+}
@@ -2,12 +2,7 @@ use pyo3::exceptions::PyException;
 use pyo3::types::PyString;
 use pyo3::{pyclass, pymethods, PyErrArguments, PyObject, PyResult, Python, ToPyObject};
 
-trait BaseException {
-    fn __str__(&self) -> PyResult<String>;
-    fn __repr__(&self) -> PyResult<String>;
-}
-
-#[pyclass(extends = PyException)]
+#[pyclass(extends = PyException, subclass)]
 pub struct SignatureError {
     message: String,
 }
@@ -18,9 +13,7 @@ impl SignatureError {
     pub fn new(message: String) -> Self {
         SignatureError { message }
     }
-}
 
-impl BaseException for SignatureError {
     fn __str__(&self) -> PyResult<String> {
         Ok(self.message.to_string())
     }
@@ -35,3 +28,125 @@ impl PyErrArguments for SignatureError {
         PyString::new(py, &self.message).to_object(py)
     }
 }
+
+/// Raised when FFMpeg is required but could not be found.
+#[pyclass(extends = SignatureError)]
+pub struct FFmpegNotFoundError {}
+
+#[pymethods]
+impl FFmpegNotFoundError {
+    #[new]
+    pub fn new(message: String) -> (Self, SignatureError) {
+        (FFmpegNotFoundError {}, SignatureError::new(message))
+    }
+}
+
+/// Raised when FFProbe is needed, to select an audio stream by language,
+/// but could not be found.
+#[pyclass(extends = SignatureError)]
+pub struct FFprobeNotFoundError {}
+
+#[pymethods]
+impl FFprobeNotFoundError {
+    #[new]
+    pub fn new(message: String) -> (Self, SignatureError) {
+        (FFprobeNotFoundError {}, SignatureError::new(message))
+    }
+}
+
+/// Raised when the input uses a codec which only FFMpeg decodes, while it
+/// could not be run.
+#[pyclass(extends = SignatureError)]
+pub struct UnsupportedCodecError {}
+
+#[pymethods]
+impl UnsupportedCodecError {
+    #[new]
+    pub fn new(message: String) -> (Self, SignatureError) {
+        (UnsupportedCodecError {}, SignatureError::new(message))
+    }
+}
+
+/// Raised when FFMpeg runs for longer than the timeout of the `Recognizer`.
+#[pyclass(extends = SignatureError)]
+pub struct FFmpegTimeoutError {}
+
+#[pymethods]
+impl FFmpegTimeoutError {
+    #[new]
+    pub fn new(message: String) -> (Self, SignatureError) {
+        (FFmpegTimeoutError {}, SignatureError::new(message))
+    }
+}
+
+/// Raised when FFMpeg outputs more than the limit of the `Recognizer`.
+#[pyclass(extends = SignatureError)]
+pub struct FFmpegOutputTooLargeError {}
+
+#[pymethods]
+impl FFmpegOutputTooLargeError {
+    #[new]
+    pub fn new(message: String) -> (Self, SignatureError) {
+        (FFmpegOutputTooLargeError {}, SignatureError::new(message))
+    }
+}
+
+/// Raised when FFMpeg fails, with its command line, exit code and errors.
+#[pyclass(extends = SignatureError)]
+pub struct FFmpegError {
+    #[pyo3(get)]
+    command_line: String,
+    #[pyo3(get)]
+    stderr: String,
+    #[pyo3(get)]
+    exit_code: Option<i32>,
+}
+
+#[pymethods]
+impl FFmpegError {
+    #[new]
+    pub fn new(
+        message: String,
+        command_line: String,
+        stderr: String,
+        exit_code: Option<i32>,
+    ) -> (Self, SignatureError) {
+        (
+            FFmpegError {
+                command_line,
+                stderr,
+                exit_code,
+            },
+            SignatureError::new(message),
+        )
+    }
+}
+
+/// Raised when a recognition request fails, with the HTTP status and body
+/// of the answer when the endpoint rejected it.
+#[cfg(feature = "client")]
+#[pyclass(extends = PyException)]
+pub struct RecognitionError {
+    message: String,
+    #[pyo3(get)]
+    status: Option<u16>,
+    #[pyo3(get)]
+    body: Option<String>,
+}
+
+#[cfg(feature = "client")]
+#[pymethods]
+impl RecognitionError {
+    #[new]
+    pub fn new(message: String, status: Option<u16>, body: Option<String>) -> Self {
+        RecognitionError {
+            message,
+            status,
+            body,
+        }
+    }
+
+    fn __str__(&self) -> PyResult<String> {
+        Ok(self.message.to_string())
+    }
+}
@@ -1,10 +1,96 @@
-use crate::fingerprinting::ffmpeg_wrapper::{decode_with_ffmpeg, decode_with_ffmpeg_from_bytes};
+use crate::fingerprinting::audio_format::AudioFormat;
+use crate::fingerprinting::audio_stream::StreamSelector;
+use crate::fingerprinting::decoder::{AudioInput, DecodeOptions, DecodedAudio, DecoderRegistry, PcmChunks};
+use crate::fingerprinting::errors::FingerprintError;
+use crate::fingerprinting::ffmpeg_wrapper::{list_audio_streams, FFMpegSettings};
 use crate::fingerprinting::hanning::HANNING_WINDOW_2048_MULTIPLIERS;
+use crate::fingerprinting::pcm::check_pcm_layout;
+use crate::fingerprinting::quality::{ClippingCounter, QualityAnalyzer};
+use crate::fingerprinting::shared_bytes::SharedBytes;
 use crate::fingerprinting::signature_format::{DecodedSignature, FrequencyBand, FrequencyPeak};
+#[cfg(feature = "symphonia")]
+use crate::fingerprinting::symphonia_decoder::list_audio_streams_with_symphonia;
+use crate::params::SearchParams;
 use chfft::RFft1D;
+use log::{debug, warn};
+use rodio::Source;
 use std::collections::HashMap;
 use std::error::Error;
-use std::io::{BufReader, Cursor};
+#[cfg(feature = "symphonia")]
+use std::io::Cursor;
+use std::time::Duration;
+
+/// Smallest buffer, in 16 KHz samples, from which peaks can be recognized
+/// (46 FFT passes of 128 samples, roughly 0.37 seconds).
+pub const MINIMUM_SAMPLES: usize = 46 * 128;
+
+/// Signature of an audio stream, or the reason it could not be computed,
+/// with the language the stream is tagged with.
+pub type StreamSignature = (Option<String>, Result<DecodedSignature, Box<dyn Error>>);
+
+/// Number of 16 KHz samples fed to the signature generator at a time.
+const FED_SAMPLES: usize = 16384;
+
+/// Rodio source reading decoded chunks as they come, so that they are
+/// resampled without being gathered first. The first decoding error ends
+/// it, and is kept in `error`.
+struct ChunksSource<'a> {
+    chunks: PcmChunks,
+    chunk: Vec<i16>,
+    position: usize,
+    /// Samples dropped before the segment to fingerprint.
+    samples_to_skip: usize,
+    sample_rate: u32,
+    channels: u16,
+    error: &'a mut Option<Box<dyn Error>>,
+    /// Clipping of the samples read, before they are converted.
+    clipping: &'a mut ClippingCounter,
+}
+
+impl Iterator for ChunksSource<'_> {
+    type Item = i16;
+
+    fn next(&mut self) -> Option<i16> {
+        while self.position >= self.chunk.len() {
+            match self.chunks.next()? {
+                Ok(chunk) => {
+                    let skipped = self.samples_to_skip.min(chunk.len());
+                    self.samples_to_skip -= skipped;
+                    self.chunk = chunk;
+                    self.position = skipped;
+                }
+                Err(error) => {
+                    *self.error = Some(error);
+                    self.chunks = Box::new(std::iter::empty());
+                    return None;
+                }
+            }
+        }
+
+        let sample = self.chunk[self.position];
+        self.position += 1;
+        self.clipping.add_sample(sample);
+        Some(sample)
+    }
+}
+
+impl Source for ChunksSource<'_> {
+    fn current_frame_len(&self) -> Option<usize> {
+        None
+    }
+
+    fn channels(&self) -> u16 {
+        self.channels
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        None
+    }
+}
 
 pub struct SignatureGenerator {
     ring_buffer_of_samples: Vec<i16>,
@@ -16,85 +102,347 @@ pub struct SignatureGenerator {
     fft_object: RFft1D<f32>,
     spread_fft_outputs_index: usize,
     num_spread_ffts_done: u32,
+    pending_samples: Vec<i16>,
+    quality_analyzer: QualityAnalyzer,
     signature: DecodedSignature,
 }
 
+impl Default for SignatureGenerator {
+    fn default() -> Self {
+        SignatureGenerator::new()
+    }
+}
+
 impl SignatureGenerator {
-    pub fn make_signature_from_bytes(bytes: Vec<u8>, segment_duration_seconds: Option<u32>) -> Result<DecodedSignature, Box<dyn Error>> {
-        // Create a cursor around the byte array for decoding
-        let cursor = Cursor::new(bytes.clone());
-
-        let decoder = rodio::Decoder::new(cursor).or_else(|_decoding_error| {
-            // Use the original bytes vector here
-            decode_with_ffmpeg_from_bytes(&bytes)
-        })?;
-
-        // Convert the decoded samples to 16 kHz mono PCM, similar to make_signature_from_file
-        // Here, we use UniformSourceIterator from rodio to downsample and convert to mono if necessary
-        let converted_file = rodio::source::UniformSourceIterator::new(decoder, 1, 16000);
-        let raw_pcm_samples: Vec<i16> = converted_file.collect();
-
-        // Process the PCM samples as in make_signature_from_buffer
-        let duration_seconds = segment_duration_seconds.unwrap_or(10);
-        let sample_rate = 16000;
-        let segment_samples = (duration_seconds * sample_rate) as usize;
-
-        let raw_pcm_samples_slice: &[i16] = if raw_pcm_samples.len() > segment_samples {
-            let middle = raw_pcm_samples.len() / 2;
-            let half_segment = segment_samples / 2;
-            if middle >= half_segment && middle + half_segment <= raw_pcm_samples.len() {
-                &raw_pcm_samples[middle - half_segment..middle + half_segment]
-            } else {
-                &raw_pcm_samples[..segment_samples]
+    pub fn make_signature_from_bytes(
+        bytes: SharedBytes,
+        options: &SearchParams,
+        decoders: &DecoderRegistry,
+        ffmpeg: &FFMpegSettings,
+    ) -> Result<DecodedSignature, Box<dyn Error>> {
+        let segment = Duration::from_secs(options.segment_duration_seconds as u64);
+        SignatureGenerator::fingerprint_input(
+            AudioInput::Bytes(&bytes),
+            decoders,
+            ffmpeg,
+            Some(segment),
+            options.stream_selector().as_ref(),
+            options.pad_last_frame,
+        )
+    }
+
+    /// Computes the signature of every audio stream of an in-memory file,
+    /// along with the language of the stream when it is tagged. The stream
+    /// selected by `options` is ignored. See `fingerprint_streams` for the
+    /// streams which fail.
+    pub fn make_signatures_of_all_streams_from_bytes(
+        bytes: SharedBytes,
+        options: &SearchParams,
+        decoders: &DecoderRegistry,
+        ffmpeg: &FFMpegSettings,
+    ) -> Result<Vec<StreamSignature>, Box<dyn Error>> {
+        let languages = SignatureGenerator::list_audio_streams_of_bytes(&bytes, ffmpeg);
+        SignatureGenerator::fingerprint_streams(AudioInput::Bytes(&bytes), languages, options, decoders, ffmpeg)
+    }
+
+    pub fn make_signature_from_file(
+        file_path: &str,
+        options: &SearchParams,
+        decoders: &DecoderRegistry,
+        ffmpeg: &FFMpegSettings,
+    ) -> Result<DecodedSignature, Box<dyn Error>> {
+        let segment = Duration::from_secs(options.segment_duration_seconds as u64);
+        SignatureGenerator::fingerprint_input(
+            AudioInput::File(file_path),
+            decoders,
+            ffmpeg,
+            Some(segment),
+            options.stream_selector().as_ref(),
+            options.pad_last_frame,
+        )
+    }
+
+    /// Computes the signature of every audio stream of a file, along with
+    /// the language of the stream when it is tagged. The stream selected by
+    /// `options` is ignored. See `fingerprint_streams` for the streams which
+    /// fail.
+    pub fn make_signatures_of_all_streams_from_file(
+        file_path: &str,
+        options: &SearchParams,
+        decoders: &DecoderRegistry,
+        ffmpeg: &FFMpegSettings,
+    ) -> Result<Vec<StreamSignature>, Box<dyn Error>> {
+        let languages = SignatureGenerator::list_audio_streams_of_file(file_path, ffmpeg);
+        SignatureGenerator::fingerprint_streams(AudioInput::File(file_path), languages, options, decoders, ffmpeg)
+    }
+
+    /// Fingerprints each of the listed streams. A stream which fails keeps
+    /// its error in place of its signature, so that the others are still
+    /// returned; the error is only returned when every stream fails, or
+    /// when the task is cancelled.
+    fn fingerprint_streams(
+        input: AudioInput,
+        languages: Vec<Option<String>>,
+        options: &SearchParams,
+        decoders: &DecoderRegistry,
+        ffmpeg: &FFMpegSettings,
+    ) -> Result<Vec<StreamSignature>, Box<dyn Error>> {
+        let segment = Duration::from_secs(options.segment_duration_seconds as u64);
+
+        let mut signatures = vec![];
+        for (index, language) in languages.into_iter().enumerate() {
+            let signature = SignatureGenerator::fingerprint_input(
+                input,
+                decoders,
+                ffmpeg,
+                Some(segment),
+                Some(&StreamSelector::Index(index as u32)),
+                options.pad_last_frame,
+            );
+
+            match signature {
+                Err(error) if matches!(error.downcast_ref(), Some(FingerprintError::FFMpegCancelled)) => {
+                    return Err(error);
+                }
+                Err(ref error) => debug!("Audio stream #{} could not be fingerprinted: {}", index, error),
+                Ok(_) => {}
             }
-        } else {
-            &raw_pcm_samples[..]
+            signatures.push((language, signature));
+        }
+
+        if signatures.iter().all(|(_, signature)| signature.is_err()) {
+            if let Some((_, Err(error))) = signatures.into_iter().next() {
+                return Err(error);
+            }
+            return Ok(vec![]);
+        }
+
+        Ok(signatures)
+    }
+
+    /// Computes the signature of interleaved PCM samples already decoded by
+    /// the caller, at any sample rate and number of channels. They are
+    /// downmixed and resampled to 16 KHz mono like decoded files.
+    pub fn make_signature_from_interleaved_pcm(
+        samples: Vec<i16>,
+        sample_rate: u32,
+        channels: u16,
+        options: &SearchParams,
+    ) -> Result<DecodedSignature, Box<dyn Error>> {
+        check_pcm_layout(samples.len(), sample_rate, channels)?;
+
+        let segment = Duration::from_secs(options.segment_duration_seconds as u64);
+        let audio = DecodedAudio::from_samples(samples, sample_rate, channels);
+        SignatureGenerator::fingerprint_decoded(audio, Some(segment), options.pad_last_frame)
+    }
+
+    /// Computes the signature of a whole file rather than of a segment of it.
+    /// The file is fingerprinted while it is decoded, so that long recordings
+    /// need no more memory than short ones.
+    pub fn make_full_length_signature_from_file(
+        file_path: &str,
+        pad_last_frame: bool,
+        decoders: &DecoderRegistry,
+        ffmpeg: &FFMpegSettings,
+    ) -> Result<DecodedSignature, Box<dyn Error>> {
+        SignatureGenerator::fingerprint_input(
+            AudioInput::File(file_path),
+            decoders,
+            ffmpeg,
+            None,
+            None,
+            pad_last_frame,
+        )
+    }
+
+    /// Decodes an input with the first decoder of the registry supporting
+    /// its format which opens it, and computes the signature of its middle
+    /// `segment` when given, of all of it otherwise. Only the selected audio
+    /// `stream` is decoded when given.
+    fn fingerprint_input(
+        input: AudioInput,
+        decoders: &DecoderRegistry,
+        ffmpeg: &FFMpegSettings,
+        segment: Option<Duration>,
+        stream: Option<&StreamSelector>,
+        pad_last_frame: bool,
+    ) -> Result<DecodedSignature, Box<dyn Error>> {
+        let mut options = DecodeOptions {
+            segment,
+            stream,
+            ffmpeg,
+            duration: None,
         };
+        let audio = decoders.decode(input, &options)?;
 
-        // Generate signature from buffer
-        let signature =
-            SignatureGenerator::make_signature_from_buffer(raw_pcm_samples_slice.to_vec());
+        let segment = match (segment, audio.duration) {
+            (Some(segment), None) => segment,
+            _ => return SignatureGenerator::fingerprint_decoded(audio, segment, pad_last_frame),
+        };
 
-        // Return the generated signature
-        Ok(signature)
+        // Where the middle of audio of unknown duration lies is only known
+        // once it was decoded entirely: measure it first, keeping only its
+        // start in case it is no longer than the segment
+
+        let (sample_rate, channels) = (audio.sample_rate, audio.channels);
+        check_pcm_layout(0, sample_rate, channels)?;
+
+        let segment_samples = (segment.as_secs_f64() * sample_rate as f64) as usize * channels as usize;
+        let mut start_samples = vec![];
+        let mut total_samples = 0u64;
+
+        for chunk in audio.chunks {
+            let chunk = chunk?;
+            let kept = (segment_samples - start_samples.len()).min(chunk.len());
+            start_samples.extend_from_slice(&chunk[..kept]);
+            total_samples += chunk.len() as u64;
+        }
+
+        if total_samples <= segment_samples as u64 {
+            let audio = DecodedAudio::from_samples(start_samples, sample_rate, channels);
+            return SignatureGenerator::fingerprint_decoded(audio, Some(segment), pad_last_frame);
+        }
+
+        let frames = total_samples / channels as u64;
+        options.duration = Some(Duration::from_secs_f64(frames as f64 / sample_rate as f64));
+        debug!("Decoding the input again to reach its middle, it lasts {:?}", options.duration);
+
+        let mut audio = decoders.decode(input, &options)?;
+        audio.duration = audio.duration.or(options.duration);
+        SignatureGenerator::fingerprint_decoded(audio, Some(segment), pad_last_frame)
     }
-    pub fn make_signature_from_file(file_path: &str, segment_duration_seconds: Option<u32>) -> Result<DecodedSignature, Box<dyn Error>> {
-        // Decode the .WAV, .MP3, .OGG or .FLAC file
 
-        let mut decoder = rodio::Decoder::new(BufReader::new(std::fs::File::open(file_path)?));
+    /// Downmixes and resamples decoded audio to 16 KHz mono, and computes
+    /// the signature of its middle `segment` when given, of all of it
+    /// otherwise, chunk by chunk. Audio decoded to 16 KHz mono already is
+    /// fingerprinted as is.
+    fn fingerprint_decoded(
+        audio: DecodedAudio,
+        segment: Option<Duration>,
+        pad_last_frame: bool,
+    ) -> Result<DecodedSignature, Box<dyn Error>> {
+        check_pcm_layout(0, audio.sample_rate, audio.channels)?;
+
+        // Skip to the middle of the audio in order to increase recognition
+        // odds. Take N (10 default) seconds of sample.
+        let start = match (segment, audio.duration) {
+            (Some(segment), Some(duration)) if duration > segment => (duration - segment) / 2,
+            _ => Duration::ZERO,
+        };
+        let start_frames = (start.as_secs_f64() * audio.sample_rate as f64) as usize;
+        let segment_samples = segment.map_or(usize::MAX, |segment| (segment.as_secs_f64() * 16000.0) as usize);
+
+        let mut decoding_error = None;
+        let mut clipping = ClippingCounter::default();
+        let mut generator = SignatureGenerator::new();
+        {
+            let is_16khz_mono = audio.sample_rate == 16000 && audio.channels == 1;
+            let source = ChunksSource {
+                chunks: audio.chunks,
+                chunk: vec![],
+                position: 0,
+                samples_to_skip: start_frames * audio.channels as usize,
+                sample_rate: audio.sample_rate,
+                channels: audio.channels,
+                error: &mut decoding_error,
+                clipping: &mut clipping,
+            };
+
+            let samples: Box<dyn Iterator<Item = i16> + '_> = if is_16khz_mono {
+                Box::new(source)
+            } else {
+                Box::new(rodio::source::UniformSourceIterator::new(source, 1, 16000))
+            };
+            let mut samples = samples.take(segment_samples);
+
+            let mut buffer = Vec::with_capacity(FED_SAMPLES);
+            loop {
+                buffer.clear();
+                buffer.extend(samples.by_ref().take(FED_SAMPLES));
+                if buffer.is_empty() {
+                    break;
+                }
+                generator.feed(&buffer)?;
+            }
+        }
 
-        if let Err(ref _decoding_error) = decoder {
-            // Try to decode with FFMpeg, if available, in case of failure with
-            // Rodio (most likely due to the use of a format unsupported by
-            // Rodio, such as .WMA or .MP4/.AAC)
+        if let Some(error) = decoding_error {
+            return Err(error);
+        }
+
+        generator.quality_analyzer.set_decoded_clipping(clipping);
+        Ok(generator.finish(pad_last_frame)?)
+    }
+
+    /// Lists the language of every audio stream of a file, `None` for the
+    /// streams which are not tagged.
+    fn list_audio_streams_of_file(file_path: &str, ffmpeg: &FFMpegSettings) -> Vec<Option<String>> {
+        match list_audio_streams(file_path, None, ffmpeg) {
+            Ok(languages) if !languages.is_empty() => return languages,
+            Ok(_) => {}
+            Err(error) => debug!("FFprobe failed to list the streams of {}: {}", file_path, error),
+        }
 
-            if let Some(new_decoder) = decode_with_ffmpeg(file_path) {
-                decoder = Ok(new_decoder);
+        #[cfg(feature = "symphonia")]
+        {
+            let extension = std::path::Path::new(file_path)
+                .extension()
+                .and_then(|extension| extension.to_str());
+
+            if let Ok(file) = std::fs::File::open(file_path) {
+                match list_audio_streams_with_symphonia(Box::new(file), extension) {
+                    Ok(languages) if !languages.is_empty() => return languages,
+                    Ok(_) => {}
+                    Err(error) => debug!("Symphonia failed to list the streams of {}: {}", file_path, error),
+                }
             }
         }
 
-        // Downsample the raw PCM samples to 16 KHz, and skip to the middle of the file
-        // in order to increase recognition odds. Take N (10 default) seconds of sample.
-        let duration_seconds = segment_duration_seconds.unwrap_or(10);
-        let sample_rate = 16000;
-        let segment_samples = (duration_seconds * sample_rate) as usize;
+        SignatureGenerator::single_audio_stream(AudioInput::File(file_path))
+    }
 
-        let converted_file = rodio::source::UniformSourceIterator::new(decoder?, 1, 16000);
-        let raw_pcm_samples: Vec<i16> = converted_file.collect();
-        let slice_len = raw_pcm_samples.len().min(segment_samples);
-        let mut raw_pcm_samples_slice: &[i16] = &raw_pcm_samples[..slice_len];
+    /// Lists the language of every audio stream of an in-memory file.
+    fn list_audio_streams_of_bytes(bytes: &SharedBytes, ffmpeg: &FFMpegSettings) -> Vec<Option<String>> {
+        match list_audio_streams("pipe:0", Some(bytes.clone()), ffmpeg) {
+            Ok(languages) if !languages.is_empty() => return languages,
+            Ok(_) => {}
+            Err(error) => debug!("FFprobe failed to list the streams of bytes: {}", error),
+        }
 
-        if raw_pcm_samples.len() > segment_samples {
-            let middle = raw_pcm_samples.len() / 2;
-            raw_pcm_samples_slice = &raw_pcm_samples[middle - segment_samples/2 .. middle + segment_samples/2];
+        #[cfg(feature = "symphonia")]
+        match list_audio_streams_with_symphonia(Box::new(Cursor::new(bytes.clone())), None) {
+            Ok(languages) if !languages.is_empty() => return languages,
+            Ok(_) => {}
+            Err(error) => debug!("Symphonia failed to list the streams of bytes: {}", error),
         }
 
-        let res = SignatureGenerator::make_signature_from_buffer(raw_pcm_samples_slice.to_vec());
-        Ok(res)
+        SignatureGenerator::single_audio_stream(AudioInput::Bytes(bytes))
+    }
+
+    /// Assumes a single audio stream when they could not be listed. Formats
+    /// Rodio decodes hold a single one, but containers may hold more.
+    fn single_audio_stream(input: AudioInput) -> Vec<Option<String>> {
+        match input.sniff_format() {
+            Ok(Some(
+                AudioFormat::Wav
+                | AudioFormat::Aiff
+                | AudioFormat::Flac
+                | AudioFormat::Ogg
+                | AudioFormat::Opus
+                | AudioFormat::Mp3
+                | AudioFormat::Aac,
+            )) => {}
+            _ => warn!(
+                "The audio streams could not be listed without FFprobe, only the first one is fingerprinted"
+            ),
+        }
+        vec![None]
     }
 
-    pub fn make_signature_from_buffer(s16_mono_16khz_buffer: Vec<i16>) -> DecodedSignature {
-        let mut this = SignatureGenerator {
+    /// A generator to which 16 KHz mono samples are fed as they are decoded,
+    /// so that only the peaks found so far are kept in memory.
+    pub fn new() -> Self {
+        SignatureGenerator {
             ring_buffer_of_samples: vec![0i16; 2048],
             ring_buffer_of_samples_index: 0,
 
@@ -110,28 +458,107 @@ impl SignatureGenerator {
 
             num_spread_ffts_done: 0,
 
+            pending_samples: Vec::with_capacity(128),
+
+            quality_analyzer: QualityAnalyzer::new(),
+
             signature: DecodedSignature {
                 sample_rate_hz: 16000,
-                number_samples: s16_mono_16khz_buffer.len() as u32,
+                number_samples: 0,
+                samples_used: 0,
                 frequency_band_to_sound_peaks: HashMap::new(),
+                quality: None,
             },
-        };
-        for chunk in s16_mono_16khz_buffer.chunks_exact(128) {
-            this.do_fft(chunk);
+        }
+    }
 
-            this.do_peak_spreading();
+    /// Processes the next 16 KHz mono samples, in chunks of any size. The
+    /// samples which do not fill a frame of 128 are kept for the next call.
+    pub fn feed(&mut self, s16_mono_16khz_samples: &[i16]) -> Result<(), FingerprintError> {
+        // The signature format counts samples in 32 bits, about 74 hours at
+        // 16 KHz, so longer input saturates rather than wrapping around
+        self.signature.number_samples = self
+            .signature
+            .number_samples
+            .saturating_add(u32::try_from(s16_mono_16khz_samples.len()).unwrap_or(u32::MAX));
+
+        let mut samples = s16_mono_16khz_samples;
+
+        if !self.pending_samples.is_empty() {
+            let missing = (128 - self.pending_samples.len()).min(samples.len());
+            self.pending_samples.extend_from_slice(&samples[..missing]);
+            samples = &samples[missing..];
+
+            if self.pending_samples.len() < 128 {
+                return Ok(());
+            }
 
-            this.num_spread_ffts_done += 1;
+            let frame = std::mem::take(&mut self.pending_samples);
+            self.process_full_frame(&frame)?;
+        }
 
-            if this.num_spread_ffts_done >= 46 {
-                this.do_peak_recognition();
-            }
+        let mut chunks = samples.chunks_exact(128);
+        for chunk in &mut chunks {
+            self.process_full_frame(chunk)?;
+        }
+        self.pending_samples.extend_from_slice(chunks.remainder());
+
+        Ok(())
+    }
+
+    /// Completes the signature once all the samples were fed. A trailing
+    /// partial frame is dropped unless `pad_last_frame` is set, in which case
+    /// it is completed with silence.
+    pub fn finish(mut self, pad_last_frame: bool) -> Result<DecodedSignature, FingerprintError> {
+        if pad_last_frame && !self.pending_samples.is_empty() {
+            let remainder = std::mem::take(&mut self.pending_samples);
+            self.quality_analyzer.add_samples(&remainder);
+            self.signature.samples_used = self
+                .signature
+                .samples_used
+                .saturating_add(remainder.len() as u32);
+
+            let mut padded_frame = [0i16; 128];
+            padded_frame[..remainder.len()].copy_from_slice(&remainder);
+            self.process_frame(&padded_frame)?;
         }
 
-        this.signature
+        // No peak can be recognized before 46 FFTs have been done, so shorter
+        // input would silently produce an empty signature
+
+        if (self.num_spread_ffts_done as usize) * 128 < MINIMUM_SAMPLES {
+            return Err(FingerprintError::InputTooShort {
+                samples: self.signature.number_samples as usize,
+                minimum_samples: MINIMUM_SAMPLES,
+            });
+        }
+
+        self.signature.quality = Some(self.quality_analyzer.finish(&self.signature));
+
+        Ok(self.signature)
     }
 
-    fn do_fft(&mut self, s16_mono_16khz_buffer: &[i16]) {
+    fn process_full_frame(&mut self, s16_mono_16khz_frame: &[i16]) -> Result<(), FingerprintError> {
+        self.quality_analyzer.add_samples(s16_mono_16khz_frame);
+        self.signature.samples_used = self.signature.samples_used.saturating_add(128);
+        self.process_frame(s16_mono_16khz_frame)
+    }
+
+    fn process_frame(&mut self, s16_mono_16khz_frame: &[i16]) -> Result<(), FingerprintError> {
+        self.do_fft(s16_mono_16khz_frame)?;
+
+        self.do_peak_spreading();
+
+        self.num_spread_ffts_done += 1;
+
+        if self.num_spread_ffts_done >= 46 {
+            self.do_peak_recognition()?;
+        }
+
+        Ok(())
+    }
+
+    fn do_fft(&mut self, s16_mono_16khz_buffer: &[i16]) -> Result<(), FingerprintError> {
         // Copy the 128 input s16le samples to the local ring buffer
 
         self.ring_buffer_of_samples
@@ -155,7 +582,12 @@ impl SignatureGenerator {
 
         let complex_fft_results = self.fft_object.forward(reordered_slice);
 
-        assert_eq!(complex_fft_results.len(), 1025);
+        if complex_fft_results.len() != 1025 {
+            return Err(FingerprintError::UnexpectedFftLength {
+                expected: 1025,
+                actual: complex_fft_results.len(),
+            });
+        }
 
         // Turn complex into reals, and put the results into a local array
 
@@ -168,8 +600,12 @@ impl SignatureGenerator {
                 .max(0.0000000001);
         }
 
+        self.quality_analyzer.add_spectrum(real_fft_results);
+
         self.fft_outputs_index += 1;
         self.fft_outputs_index &= 255;
+
+        Ok(())
     }
 
     fn do_peak_spreading(&mut self) {
@@ -203,7 +639,7 @@ impl SignatureGenerator {
         self.spread_fft_outputs_index &= 255;
     }
 
-    fn do_peak_recognition(&mut self) {
+    fn do_peak_recognition(&mut self) -> Result<(), FingerprintError> {
         // Note: when substracting an array index, casting to signed is needed
         // to avoid underflow panics at runtime.
 
@@ -258,6 +694,15 @@ impl SignatureGenerator {
 
                         let peak_variation_1: f32 =
                             peak_magnitude * 2.0 - peak_magnitude_before - peak_magnitude_after;
+
+                        if peak_variation_1.is_nan() || peak_variation_1 < 0.0 {
+                            return Err(FingerprintError::InvalidPeakVariation {
+                                fft_pass_number,
+                                bin_position,
+                                variation: peak_variation_1,
+                            });
+                        }
+
                         let peak_variation_2: f32 = (peak_magnitude_after - peak_magnitude_before)
                             * 32.0
                             / peak_variation_1;
@@ -266,8 +711,6 @@ impl SignatureGenerator {
                             (bin_position as i32 * 64) + (peak_variation_2 as i32)
                         ) as u16;
 
-                        assert!(peak_variation_1 >= 0.0);
-
                         // Convert back a FFT bin to a frequency, given a 16 KHz sample
                         // rate, 1024 useful bins and the multiplication by 64 made before
                         // storing the information
@@ -289,14 +732,10 @@ impl SignatureGenerator {
                             }
                         };
 
-                        self.signature.frequency_band_to_sound_peaks
-                            .entry(frequency_band)
-                            .or_default();
-
                         self.signature
                             .frequency_band_to_sound_peaks
-                            .get_mut(&frequency_band)
-                            .unwrap()
+                            .entry(frequency_band)
+                            .or_default()
                             .push(FrequencyPeak {
                                 fft_pass_number,
                                 peak_magnitude: peak_magnitude as u16,
@@ -306,5 +745,162 @@ impl SignatureGenerator {
                 }
             }
         }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Feeds the samples in chunks of random sizes, then encodes the
+    /// signature, which must fail with an error rather than panic.
+    fn fingerprint(
+        samples: &[i16],
+        rng: &mut fastrand::Rng,
+        pad_last_frame: bool,
+    ) -> Result<DecodedSignature, Box<dyn Error>> {
+        let mut generator = SignatureGenerator::new();
+
+        let mut remaining = samples;
+        while !remaining.is_empty() {
+            let chunk_size = rng.usize(1..=remaining.len().min(5000));
+            generator.feed(&remaining[..chunk_size])?;
+            remaining = &remaining[chunk_size..];
+        }
+
+        let signature = generator.finish(pad_last_frame)?;
+        signature.encode_to_uri()?;
+        Ok(signature)
+    }
+
+    fn edge_case_buffers(rng: &mut fastrand::Rng) -> Vec<Vec<i16>> {
+        vec![
+            vec![],
+            vec![0],
+            vec![i16::MIN],
+            vec![0; MINIMUM_SAMPLES - 1],
+            vec![0; 48000],
+            vec![1000; 48000],
+            vec![i16::MAX; 48000],
+            vec![i16::MIN; 48000],
+            (0..48000).map(|index| if index % 2 == 0 { i16::MAX } else { i16::MIN }).collect(),
+            (0..48000).map(|index| if (index / 40) % 2 == 0 { i16::MAX } else { i16::MIN }).collect(),
+            (0..48000).map(|_| rng.i16(..)).collect(),
+        ]
+    }
+
+    #[test]
+    fn random_pcm_never_panics() {
+        let mut rng = fastrand::Rng::with_seed(0x5EED);
+
+        for _ in 0..40 {
+            let length = rng.usize(0..=64000);
+            let amplitude = rng.i16(1..=i16::MAX);
+            let samples: Vec<i16> = (0..length).map(|_| rng.i16(-amplitude..=amplitude)).collect();
+            let pad_last_frame = rng.bool();
+
+            if let Err(error) = fingerprint(&samples, &mut rng, pad_last_frame) {
+                assert!(error.downcast_ref::<FingerprintError>().is_some(), "{}", error);
+            }
+        }
+    }
+
+    #[test]
+    fn edge_case_pcm_never_panics() {
+        let mut rng = fastrand::Rng::with_seed(0x5EED);
+
+        for samples in edge_case_buffers(&mut rng) {
+            for pad_last_frame in [false, true] {
+                let result = fingerprint(&samples, &mut rng, pad_last_frame);
+
+                // A padded partial frame counts as a whole one
+                let frames = match pad_last_frame {
+                    true => (samples.len() + 127) / 128,
+                    false => samples.len() / 128,
+                };
+                if frames * 128 < MINIMUM_SAMPLES {
+                    let error = result.err().expect("short input was fingerprinted");
+                    assert!(matches!(
+                        error.downcast_ref::<FingerprintError>(),
+                        Some(FingerprintError::InputTooShort { .. })
+                    ));
+                } else if let Err(error) = result {
+                    assert!(error.downcast_ref::<FingerprintError>().is_some(), "{}", error);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn interleaved_pcm_never_panics() {
+        let mut rng = fastrand::Rng::with_seed(0x5EED);
+        let options = SearchParams::with_segment(3);
+
+        for samples in edge_case_buffers(&mut rng) {
+            for (sample_rate, channels) in [(16000, 1), (44100, 2), (8000, 1), (48000, 6)] {
+                let samples = samples[..samples.len() - samples.len() % channels as usize].to_vec();
+                let frames = samples.len() / channels as usize;
+                let short = frames * 16000 / (sample_rate as usize) < MINIMUM_SAMPLES;
+
+                let result = SignatureGenerator::make_signature_from_interleaved_pcm(
+                    samples,
+                    sample_rate,
+                    channels,
+                    &options,
+                );
+                match result {
+                    Ok(signature) => {
+                        assert!(!short);
+                        signature.encode_to_uri().unwrap();
+                    }
+                    Err(error) => {
+                        assert!(error.downcast_ref::<FingerprintError>().is_some(), "{}", error)
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn sample_counters_saturate() {
+        let mut generator = SignatureGenerator::new();
+        generator.signature.number_samples = u32::MAX - 100;
+        generator.signature.samples_used = u32::MAX - 100;
+
+        generator.feed(&[0; 1000]).unwrap();
+        assert_eq!(generator.signature.number_samples, u32::MAX);
+        assert_eq!(generator.signature.samples_used, u32::MAX);
+
+        generator.signature.encode_to_binary().unwrap();
+    }
+
+    #[test]
+    fn negative_peak_variation_fails_the_signature() {
+        let mut generator = SignatureGenerator::new();
+        generator.num_spread_ffts_done = 46;
+
+        // A bin standing out from the spread spectra, but quieter than its
+        // immediate neighbours, passes the peak tests with a negative
+        // variation
+
+        let fft_minus_46 = &mut generator.fft_outputs[(0i32 - 46) as usize & 255];
+        fft_minus_46[499] = 1e6;
+        fft_minus_46[500] = 1.0;
+        fft_minus_46[501] = 1e6;
+
+        match generator.do_peak_recognition() {
+            Err(FingerprintError::InvalidPeakVariation {
+                fft_pass_number,
+                bin_position,
+                variation,
+            }) => {
+                assert_eq!(fft_pass_number, 0);
+                assert_eq!(bin_position, 500);
+                assert!(variation < 0.0);
+            }
+            other => panic!("expected InvalidPeakVariation, got {:?}", other),
+        }
     }
 }
@@ -0,0 +1,233 @@
+use crate::fingerprinting::landmark_index::{
+    landmarks_from_signature, Landmark, LandmarkIndex, Votes, SECONDS_PER_FFT_PASS,
+};
+use crate::fingerprinting::signature_format::DecodedSignature;
+use std::collections::HashMap;
+
+/// Fewer aligned landmarks than this are treated as chance collisions.
+const MIN_ALIGNED_LANDMARKS: u32 = 10;
+
+/// Length of the windows in which the offset is measured separately to
+/// estimate drift, in FFT passes (30 seconds).
+const DRIFT_WINDOW_FFT_PASSES: u32 = 3750;
+
+/// Largest distance from the global offset searched in each drift window,
+/// in FFT passes (2 seconds).
+const DRIFT_SEARCH_FFT_PASSES: i64 = 250;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Alignment {
+    /// Position of the start of the other recording within the reference
+    /// one, in seconds. Negative when the other recording starts first.
+    pub offset_seconds: f64,
+    /// Share of landmarks agreeing on the offset, from 0 to 1.
+    pub confidence: f32,
+    pub votes: u32,
+    /// Clock drift of the other recording relative to the reference one,
+    /// in parts per million, when it could be estimated.
+    pub drift_ppm: Option<f64>,
+}
+
+/// Offset histogram of one track, from the votes of a query.
+fn offset_histogram(votes: Votes) -> HashMap<i64, u32> {
+    votes
+        .into_iter()
+        .map(|((_track, offset), count)| (offset, count))
+        .collect()
+}
+
+/// Finds the offset with the most votes, counting neighbouring offsets as
+/// well since peaks of unsynchronized recordings can land one FFT pass
+/// apart. Returns the offset refined to a fraction of an FFT pass and the
+/// vote count.
+fn best_offset(
+    histogram: &HashMap<i64, u32>,
+    allowed: impl Fn(i64) -> bool,
+) -> Option<(f64, u32)> {
+    let count = |offset: i64| histogram.get(&offset).copied().unwrap_or(0);
+
+    let (offset, votes) = histogram
+        .keys()
+        .filter(|offset| allowed(**offset))
+        .map(|offset| (*offset, count(offset - 1) + count(*offset) + count(offset + 1)))
+        .max_by(|(offset_a, votes_a), (offset_b, votes_b)| {
+            votes_a.cmp(votes_b).then_with(|| offset_b.cmp(offset_a))
+        })?;
+
+    // Parabolic interpolation between the neighbouring bins
+
+    let (before, at, after) = (
+        count(offset - 1) as f64,
+        count(offset) as f64,
+        count(offset + 1) as f64,
+    );
+    let curvature = before - 2.0 * at + after;
+    let refinement = if curvature < 0.0 {
+        (0.5 * (before - after) / curvature).clamp(-0.5, 0.5)
+    } else {
+        0.0
+    };
+
+    Some((offset as f64 + refinement, votes))
+}
+
+fn estimate_drift_ppm(
+    index: &LandmarkIndex,
+    landmarks: &[Landmark],
+    global_offset: f64,
+) -> Option<f64> {
+    let mut windows: HashMap<u32, Vec<Landmark>> = HashMap::new();
+    for landmark in landmarks {
+        windows
+            .entry(landmark.anchor_fft_pass_number / DRIFT_WINDOW_FFT_PASSES)
+            .or_default()
+            .push(*landmark);
+    }
+
+    let mut points = vec![];
+    for (window, window_landmarks) in windows {
+        let mut votes = Votes::new();
+        index.vote(&window_landmarks, &mut votes);
+
+        let histogram = offset_histogram(votes);
+        let nearby = |offset: i64| (offset as f64 - global_offset).abs() <= DRIFT_SEARCH_FFT_PASSES as f64;
+
+        if let Some((offset, votes)) = best_offset(&histogram, nearby) {
+            if votes >= MIN_ALIGNED_LANDMARKS {
+                let center = (window as f64 + 0.5) * DRIFT_WINDOW_FFT_PASSES as f64;
+                points.push((center, offset));
+            }
+        }
+    }
+
+    if points.len() < 2 {
+        return None;
+    }
+
+    // Least-squares slope of the offset over time
+
+    let count = points.len() as f64;
+    let mean_time = points.iter().map(|(time, _)| time).sum::<f64>() / count;
+    let mean_offset = points.iter().map(|(_, offset)| offset).sum::<f64>() / count;
+    let covariance: f64 = points
+        .iter()
+        .map(|(time, offset)| (time - mean_time) * (offset - mean_offset))
+        .sum();
+    let variance: f64 = points.iter().map(|(time, _)| (time - mean_time).powi(2)).sum();
+
+    Some(covariance / variance * 1_000_000.0)
+}
+
+/// Finds the time offset between two recordings of the same event from
+/// their matching peaks, or `None` if they share no audio.
+pub fn align_signatures(
+    reference: &DecodedSignature,
+    other: &DecodedSignature,
+    estimate_drift: bool,
+) -> Option<Alignment> {
+    let reference_landmarks = landmarks_from_signature(reference);
+    let other_landmarks = landmarks_from_signature(other);
+
+    let mut index = LandmarkIndex::new();
+    index.add_landmarks("reference", &reference_landmarks);
+
+    let mut votes = Votes::new();
+    index.vote(&other_landmarks, &mut votes);
+
+    let (offset, votes) = best_offset(&offset_histogram(votes), |_| true)?;
+    if votes < MIN_ALIGNED_LANDMARKS {
+        return None;
+    }
+
+    let smallest = reference_landmarks
+        .len()
+        .min(other_landmarks.len())
+        .max(1);
+
+    Some(Alignment {
+        offset_seconds: offset * SECONDS_PER_FFT_PASS as f64,
+        confidence: (votes as f32 / smallest as f32).min(1.0),
+        votes,
+        drift_ppm: if estimate_drift {
+            estimate_drift_ppm(&index, &other_landmarks, offset)
+        } else {
+            None
+        },
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fingerprinting::algorithm::SignatureGenerator;
+
+    /// A signal of random tones over noise, changing every quarter second.
+    fn random_tones(rng: &mut fastrand::Rng, seconds: usize) -> Vec<f32> {
+        let mut samples = Vec::with_capacity(seconds * 16000);
+        for _ in 0..seconds * 4 {
+            let frequencies: Vec<f32> = (0..3).map(|_| rng.f32() * 4500.0 + 300.0).collect();
+            for index in 0..4000 {
+                let time = (samples.len() + index) as f32 / 16000.0;
+                let tones: f32 = frequencies
+                    .iter()
+                    .map(|frequency| (std::f32::consts::TAU * frequency * time).sin())
+                    .sum();
+                samples.push(tones * 6000.0 + (rng.f32() - 0.5) * 2000.0);
+            }
+        }
+        samples
+    }
+
+    fn fingerprint(samples: &[f32]) -> DecodedSignature {
+        let samples: Vec<i16> = samples.iter().map(|sample| *sample as i16).collect();
+        let mut generator = SignatureGenerator::new();
+        generator.feed(&samples).unwrap();
+        generator.finish(false).unwrap()
+    }
+
+    /// Reads the signal at `speed` times its rate, by linear interpolation.
+    fn resample(samples: &[f32], speed: f64) -> Vec<f32> {
+        let length = ((samples.len() - 1) as f64 / speed) as usize;
+        (0..length)
+            .map(|index| {
+                let position = index as f64 * speed;
+                let (before, fraction) = (position as usize, position.fract() as f32);
+                samples[before] * (1.0 - fraction) + samples[before + 1] * fraction
+            })
+            .collect()
+    }
+
+    #[test]
+    fn finds_the_offset_of_a_shifted_copy() {
+        let mut rng = fastrand::Rng::with_seed(0x5EED);
+        let samples = random_tones(&mut rng, 20);
+        let reference = fingerprint(&samples);
+
+        // The copy starts 3 seconds into the reference, and the other way around
+        let copy = fingerprint(&samples[3 * 16000..15 * 16000]);
+
+        let alignment = align_signatures(&reference, &copy, false).unwrap();
+        assert!((alignment.offset_seconds - 3.0).abs() < 0.02, "{:?}", alignment);
+        assert!(alignment.confidence > 0.5);
+        assert_eq!(alignment.drift_ppm, None);
+
+        let alignment = align_signatures(&copy, &reference, false).unwrap();
+        assert!((alignment.offset_seconds + 3.0).abs() < 0.02, "{:?}", alignment);
+    }
+
+    #[test]
+    fn estimates_the_drift_of_a_resampled_copy() {
+        let mut rng = fastrand::Rng::with_seed(0x5EED);
+        let samples = random_tones(&mut rng, 60);
+        let reference = fingerprint(&samples);
+
+        // Recorded by a clock running 0.1 % slow, so its audio is shorter
+        let copy = fingerprint(&resample(&samples, 1.001));
+
+        // The copy drifts by up to 60 ms over the minute
+        let alignment = align_signatures(&reference, &copy, true).unwrap();
+        assert!((0.0..0.07).contains(&alignment.offset_seconds), "{:?}", alignment);
+        let drift_ppm = alignment.drift_ppm.unwrap();
+        assert!((drift_ppm - 1000.0).abs() < 150.0, "{:?}", alignment);
+    }
+}
@@ -0,0 +1,68 @@
+/// Container or stream format of an input, as recognized from its first
+/// bytes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AudioFormat {
+    Wav,
+    Aiff,
+    Flac,
+    /// Ogg, except for Opus.
+    Ogg,
+    /// Opus in an Ogg container, only FFMpeg decodes it.
+    Opus,
+    Mp3,
+    /// Raw AAC in ADTS frames.
+    Aac,
+    /// MP4, M4A and QuickTime.
+    Mp4,
+    /// Matroska and WebM.
+    Matroska,
+    /// WMA and other ASF files.
+    Asf,
+}
+
+/// Number of bytes `sniff_format` needs to recognize every format.
+pub const SNIFFED_BYTES: usize = 36;
+
+/// Recognizes the format of an input from its magic bytes, `None` when they
+/// match no known format.
+pub fn sniff_format(header: &[u8]) -> Option<AudioFormat> {
+    if header.len() >= 12 && &header[0..4] == b"RIFF" && &header[8..12] == b"WAVE" {
+        return Some(AudioFormat::Wav);
+    }
+    if header.len() >= 12 && &header[0..4] == b"FORM" && matches!(&header[8..12], b"AIFF" | b"AIFC") {
+        return Some(AudioFormat::Aiff);
+    }
+    if header.starts_with(b"fLaC") {
+        return Some(AudioFormat::Flac);
+    }
+    // The first page of an Ogg stream holds the identification header of
+    // its codec, which starts at byte 28 when the page has a single segment
+    if header.len() >= 36 && header.starts_with(b"OggS") && &header[28..36] == b"OpusHead" {
+        return Some(AudioFormat::Opus);
+    }
+    if header.starts_with(b"OggS") {
+        return Some(AudioFormat::Ogg);
+    }
+    if header.len() >= 8 && &header[4..8] == b"ftyp" {
+        return Some(AudioFormat::Mp4);
+    }
+    if header.starts_with(&[0x1A, 0x45, 0xDF, 0xA3]) {
+        return Some(AudioFormat::Matroska);
+    }
+    if header.starts_with(&[0x30, 0x26, 0xB2, 0x75, 0x8E, 0x66, 0xCF, 0x11]) {
+        return Some(AudioFormat::Asf);
+    }
+    // ID3 tags precede MP3 frames
+    if header.starts_with(b"ID3") {
+        return Some(AudioFormat::Mp3);
+    }
+
+    // MPEG audio and ADTS frames both start with a sync word, and differ by
+    // the layer, always 0 for ADTS
+
+    match header {
+        [0xFF, second, ..] if second & 0xF6 == 0xF0 => Some(AudioFormat::Aac),
+        [0xFF, second, ..] if second & 0xE0 == 0xE0 && second & 0x06 != 0 => Some(AudioFormat::Mp3),
+        _ => None,
+    }
+}
@@ -0,0 +1,28 @@
+/// Selects the audio stream to decode, in containers holding several of
+/// them (dubbed languages, commentary tracks).
+#[derive(Debug, Clone, PartialEq)]
+pub enum StreamSelector {
+    /// Position among the audio streams, from 0.
+    Index(u32),
+    /// Language tag, such as "eng". The first stream in that language is
+    /// used.
+    Language(String),
+}
+
+impl StreamSelector {
+    /// Returns the position of the selected stream, given the language of
+    /// every audio stream.
+    pub fn position(&self, languages: &[Option<String>]) -> Option<u32> {
+        match self {
+            StreamSelector::Index(index) => Some(*index).filter(|index| (*index as usize) < languages.len()),
+            StreamSelector::Language(language) => languages
+                .iter()
+                .position(|stream_language| {
+                    stream_language
+                        .as_ref()
+                        .is_some_and(|stream_language| stream_language.eq_ignore_ascii_case(language))
+                })
+                .map(|position| position as u32),
+        }
+    }
+}
@@ -0,0 +1,345 @@
+use std::error::Error;
+use std::fmt;
+use std::time::Duration;
+
+use reqwest::header::{HeaderMap, HeaderValue, ACCEPT, CONTENT_LANGUAGE};
+use serde::de::DeserializeOwned;
+
+use crate::fingerprinting::communication::{
+    is_valid_request_id, random_uuid, RecognitionRequest, RequestLocale, Signature,
+};
+
+/// Base URL of the public discovery endpoint.
+pub const DEFAULT_BASE_URL: &str = "https://amp.shazam.com";
+
+/// User agent of a desktop browser, as the web client sends.
+pub const DEFAULT_USER_AGENT: &str =
+    "Mozilla/5.0 (Windows NT 10.0; Win64; x64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/120.0.0.0 Safari/537.36";
+
+/// Requests are abandoned when they take longer than this.
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(20);
+
+/// Errors raised while submitting a recognition request.
+#[derive(Debug)]
+pub enum ClientError {
+    InvalidBaseUrl(String),
+    InvalidLocale(String),
+    Request(reqwest::Error),
+    Status { status: u16, body: String },
+    InvalidResponse(serde_json::Error),
+}
+
+impl fmt::Display for ClientError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ClientError::InvalidBaseUrl(url) => {
+                write!(f, "Invalid base URL {:?}, expected an http or https URL", url)
+            }
+            ClientError::InvalidLocale(part) => write!(
+                f,
+                "{:?} cannot be put in a request path, only letters, digits and dashes can",
+                part
+            ),
+            ClientError::Request(error) => write!(f, "Recognition request failed: {}", error),
+            ClientError::Status { status, .. } => {
+                write!(f, "Discovery endpoint answered with status {}", status)
+            }
+            ClientError::InvalidResponse(error) => {
+                write!(f, "Discovery endpoint answered with an unexpected body: {}", error)
+            }
+        }
+    }
+}
+
+impl Error for ClientError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            ClientError::Request(error) => Some(error),
+            ClientError::InvalidResponse(error) => Some(error),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ClientSettings {
+    /// Scheme and host of the discovery endpoint, such as a local mock
+    /// server or a proxy.
+    pub base_url: String,
+    pub user_agent: String,
+    pub locale: RequestLocale,
+    /// Requests are abandoned when they take longer than this.
+    pub timeout: Option<Duration>,
+}
+
+impl Default for ClientSettings {
+    fn default() -> Self {
+        ClientSettings {
+            base_url: DEFAULT_BASE_URL.to_string(),
+            user_agent: DEFAULT_USER_AGENT.to_string(),
+            locale: RequestLocale::default(),
+            timeout: Some(DEFAULT_TIMEOUT),
+        }
+    }
+}
+
+/// Submits recognition requests to the discovery endpoint. Its connections
+/// are reused across requests.
+#[derive(Debug, Clone)]
+pub struct RecognitionClient {
+    settings: ClientSettings,
+    /// Identifies the installation in every request, as the apps do.
+    installation_id: String,
+    http: reqwest::Client,
+}
+
+impl RecognitionClient {
+    pub fn new(settings: ClientSettings) -> Result<Self, ClientError> {
+        let base_url = settings.base_url.trim_end_matches('/');
+        match reqwest::Url::parse(base_url) {
+            Ok(url) if matches!(url.scheme(), "http" | "https") && url.has_host() => {}
+            _ => return Err(ClientError::InvalidBaseUrl(settings.base_url)),
+        }
+
+        let locale = &settings.locale;
+        for part in [&locale.language, &locale.country, &locale.device] {
+            if !is_valid_request_id(part) {
+                return Err(ClientError::InvalidLocale(part.clone()));
+            }
+        }
+
+        let mut headers = HeaderMap::new();
+        headers.insert(ACCEPT, HeaderValue::from_static("application/json"));
+        // Validated above, only letters, digits and dashes
+        headers.insert(
+            CONTENT_LANGUAGE,
+            HeaderValue::from_str(&locale.language).unwrap(),
+        );
+
+        let mut builder = reqwest::Client::builder()
+            .user_agent(settings.user_agent.clone())
+            .default_headers(headers);
+        if let Some(timeout) = settings.timeout {
+            builder = builder.timeout(timeout);
+        }
+        let http = builder.build().map_err(ClientError::Request)?;
+
+        Ok(RecognitionClient {
+            settings: ClientSettings {
+                base_url: base_url.to_string(),
+                ..settings
+            },
+            installation_id: random_uuid(),
+            http,
+        })
+    }
+
+    pub fn settings(&self) -> &ClientSettings {
+        &self.settings
+    }
+
+    /// POSTs the request recognizing a signature and returns the answer of
+    /// the endpoint, parsed as `T`. Each request has a new tag identifier.
+    pub async fn recognize<T: DeserializeOwned>(&self, signature: &Signature) -> Result<T, ClientError> {
+        let request = RecognitionRequest::new(
+            signature,
+            &self.settings.locale,
+            &random_uuid(),
+            &self.installation_id,
+        );
+
+        let response = self
+            .http
+            .post(format!("{}{}", self.settings.base_url, request.path))
+            .json(&request.body)
+            .send()
+            .await
+            .map_err(ClientError::Request)?;
+
+        let status = response.status();
+        let body = response.text().await.map_err(ClientError::Request)?;
+        if !status.is_success() {
+            return Err(ClientError::Status {
+                status: status.as_u16(),
+                body,
+            });
+        }
+
+        serde_json::from_str(&body).map_err(ClientError::InvalidResponse)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fingerprinting::communication::{GeolocationResponse, SignatureSong, DISCOVERY_QUERY};
+    use crate::match_response::MatchResponse;
+    use std::io::{BufRead, BufReader, Read, Write};
+    use std::net::TcpListener;
+    use std::sync::mpsc::{self, Receiver};
+    use std::thread;
+
+    /// A request as received by the mock server, with lower case header
+    /// names.
+    struct ReceivedRequest {
+        request_line: String,
+        headers: Vec<(String, String)>,
+        body: String,
+    }
+
+    impl ReceivedRequest {
+        fn header(&self, name: &str) -> Option<&str> {
+            self.headers
+                .iter()
+                .find(|(header, _)| header == name)
+                .map(|(_, value)| value.as_str())
+        }
+    }
+
+    /// Starts a server on a local port which answers a single request with
+    /// the given status and body, after `delay`. Returns its base URL, and
+    /// the request once it is received.
+    fn serve_once(status: &'static str, body: &'static str, delay: Duration) -> (String, Receiver<ReceivedRequest>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let base_url = format!("http://{}", listener.local_addr().unwrap());
+        let (sender, received) = mpsc::channel();
+
+        thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream);
+
+            let mut request_line = String::new();
+            reader.read_line(&mut request_line).unwrap();
+
+            let mut headers = vec![];
+            loop {
+                let mut line = String::new();
+                reader.read_line(&mut line).unwrap();
+                match line.trim_end().split_once(':') {
+                    Some((name, value)) => headers.push((name.to_lowercase(), value.trim().to_string())),
+                    None => break,
+                }
+            }
+
+            let content_length = headers
+                .iter()
+                .find(|(name, _)| name == "content-length")
+                .map_or(0, |(_, value)| value.parse().unwrap());
+            let mut request_body = vec![0u8; content_length];
+            reader.read_exact(&mut request_body).unwrap();
+
+            let _ = sender.send(ReceivedRequest {
+                request_line: request_line.trim_end().to_string(),
+                headers,
+                body: String::from_utf8(request_body).unwrap(),
+            });
+
+            thread::sleep(delay);
+            let response = format!(
+                "HTTP/1.1 {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                status,
+                body.len(),
+                body
+            );
+            // The client is gone when it timed out
+            let _ = reader.into_inner().write_all(response.as_bytes());
+        });
+
+        (base_url, received)
+    }
+
+    fn client(base_url: String, timeout: Duration) -> RecognitionClient {
+        RecognitionClient::new(ClientSettings {
+            base_url,
+            user_agent: "shazamio-core-tests".to_string(),
+            locale: RequestLocale {
+                language: "fr-FR".to_string(),
+                country: "FR".to_string(),
+                device: "android".to_string(),
+            },
+            timeout: Some(timeout),
+        })
+        .unwrap()
+    }
+
+    fn signature() -> Signature {
+        Signature {
+            geolocation: GeolocationResponse::default(),
+            signature: SignatureSong {
+                samples: 3000,
+                samples_used: 48000,
+                timestamp: 1700000000000,
+                uri: "data:audio/vnd.shazam.sig;base64,gCX".to_string(),
+            },
+            timestamp: 1700000000000,
+            timezone: "Europe/Paris".to_string(),
+            quality: None,
+        }
+    }
+
+    fn recognize(client: &RecognitionClient) -> Result<MatchResponse, ClientError> {
+        tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap()
+            .block_on(client.recognize::<MatchResponse>(&signature()))
+    }
+
+    #[test]
+    fn posts_the_signature_and_parses_the_answer() {
+        let (base_url, received) = serve_once(
+            "200 OK",
+            r#"{"matches": [], "tagid": "A1B2", "retryms": 4000}"#,
+            Duration::ZERO,
+        );
+
+        let response = recognize(&client(base_url, Duration::from_secs(10))).unwrap();
+        assert!(response.matches.is_empty());
+        assert_eq!(response.tagid.as_deref(), Some("A1B2"));
+        assert_eq!(response.retryms, Some(4000));
+
+        let request = received.recv().unwrap();
+        let mut request_line = request.request_line.split(' ');
+        let (method, path) = (request_line.next().unwrap(), request_line.next().unwrap());
+        assert_eq!(method, "POST");
+        assert!(path.starts_with("/discovery/v5/fr-FR/FR/android/-/tag/"));
+        assert!(path.ends_with(&format!("?{}", DISCOVERY_QUERY)));
+        assert_eq!(request.header("content-language"), Some("fr-FR"));
+        assert_eq!(request.header("user-agent"), Some("shazamio-core-tests"));
+        assert_eq!(request.header("content-type"), Some("application/json"));
+
+        let body: serde_json::Value = serde_json::from_str(&request.body).unwrap();
+        assert_eq!(body["signature"]["uri"], "data:audio/vnd.shazam.sig;base64,gCX");
+        assert_eq!(body["signature"]["samplems"], 3000);
+        assert_eq!(body["signature"]["timestamp"], 1700000000000u64);
+        assert_eq!(body["timezone"], "Europe/Paris");
+        assert_eq!(body["context"], serde_json::json!({}));
+    }
+
+    #[test]
+    fn keeps_the_body_of_error_statuses() {
+        let (base_url, _received) = serve_once(
+            "429 Too Many Requests",
+            r#"{"error": "slow down"}"#,
+            Duration::ZERO,
+        );
+
+        match recognize(&client(base_url, Duration::from_secs(10))) {
+            Err(ClientError::Status { status, body }) => {
+                assert_eq!(status, 429);
+                assert_eq!(body, r#"{"error": "slow down"}"#);
+            }
+            other => panic!("expected a status error, got {:?}", other.map(|_| ())),
+        }
+    }
+
+    #[test]
+    fn times_out_slow_answers() {
+        let (base_url, _received) = serve_once("200 OK", "{}", Duration::from_secs(5));
+
+        match recognize(&client(base_url, Duration::from_millis(200))) {
+            Err(ClientError::Request(error)) => assert!(error.is_timeout()),
+            other => panic!("expected a timeout, got {:?}", other.map(|_| ())),
+        }
+    }
+}
@@ -1,19 +1,112 @@
+use serde::{Deserialize, Serialize};
 use std::error::Error;
-use std::time::SystemTime;
+use std::time::{SystemTime, SystemTimeError};
 
+use crate::fingerprinting::quality::QualityReport;
 use crate::fingerprinting::signature_format::DecodedSignature;
 
-#[derive(Debug)]
+/// Timezone sent when none is configured.
+pub const DEFAULT_TIMEZONE: &str = "Europe/Paris";
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct GeolocationResponse {
-    pub(crate) altitude: i16,
-    pub(crate) latitude: i8,
-    pub(crate) longitude: i8,
+    /// Meters above sea level.
+    pub(crate) altitude: f64,
+    /// Degrees, positive to the north.
+    pub(crate) latitude: f64,
+    /// Degrees, positive to the east.
+    pub(crate) longitude: f64,
+}
+
+impl Default for GeolocationResponse {
+    /// Somewhere in central France, where the requests were always sent
+    /// from before this was configurable.
+    fn default() -> Self {
+        GeolocationResponse {
+            altitude: 300.0,
+            latitude: 45.0,
+            longitude: 2.0,
+        }
+    }
+}
+
+/// Source of the timestamps of the requests.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Clock {
+    /// The current time of the system.
+    #[default]
+    System,
+    /// Always the same time, in milliseconds since the Unix epoch, so that
+    /// requests can be reproduced.
+    Fixed(u64),
+}
+
+impl Clock {
+    /// Milliseconds since the Unix epoch.
+    pub fn now_ms(&self) -> Result<u64, SystemTimeError> {
+        match self {
+            Clock::System => Ok(SystemTime::now()
+                .duration_since(SystemTime::UNIX_EPOCH)?
+                .as_millis() as u64),
+            Clock::Fixed(timestamp_ms) => Ok(*timestamp_ms),
+        }
+    }
+}
+
+/// Where and when the recognition request says it comes from. Results
+/// depend on the region.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RequestContext {
+    pub(crate) geolocation: GeolocationResponse,
+    /// IANA name of the timezone, such as "America/New_York".
+    pub(crate) timezone: String,
+    pub(crate) clock: Clock,
+}
+
+impl Default for RequestContext {
+    fn default() -> Self {
+        RequestContext {
+            geolocation: GeolocationResponse::default(),
+            timezone: DEFAULT_TIMEZONE.to_string(),
+            clock: Clock::System,
+        }
+    }
+}
+
+/// Returns the IANA name of the timezone of the system, from the `TZ`
+/// environment variable, `/etc/timezone` or the target of the
+/// `/etc/localtime` link, when one of them tells.
+pub fn system_timezone() -> Option<String> {
+    let is_iana_name = |name: &str| !name.is_empty() && name.contains('/') && !name.starts_with('/');
+
+    if let Ok(timezone) = std::env::var("TZ") {
+        // A leading colon means the rest is implementation defined, usually
+        // the name of a zoneinfo file
+        let timezone = timezone.trim_start_matches(':');
+        if is_iana_name(timezone) || timezone == "UTC" {
+            return Some(timezone.to_string());
+        }
+    }
+
+    if let Ok(timezone) = std::fs::read_to_string("/etc/timezone") {
+        let timezone = timezone.trim();
+        if is_iana_name(timezone) {
+            return Some(timezone.to_string());
+        }
+    }
+
+    let target = std::fs::read_link("/etc/localtime").ok()?;
+    let target = target.to_str()?;
+    let (_, timezone) = target.split_once("zoneinfo/")?;
+    is_iana_name(timezone).then(|| timezone.to_string())
 }
 
 #[derive(Debug)]
 pub struct SignatureSong {
     pub(crate) samples: u32,
-    pub(crate) timestamp: u32,
+    pub(crate) samples_used: u32,
+    /// Milliseconds since the Unix epoch.
+    pub(crate) timestamp: u64,
     pub(crate) uri: String,
 }
 
@@ -21,28 +114,130 @@ pub struct SignatureSong {
 pub struct Signature {
     pub(crate) geolocation: GeolocationResponse,
     pub(crate) signature: SignatureSong,
-    pub(crate) timestamp: u32,
+    /// Milliseconds since the Unix epoch.
+    pub(crate) timestamp: u64,
     pub(crate) timezone: String,
+    pub(crate) quality: Option<QualityReport>,
 }
 
-pub fn get_signature_json(signature: &DecodedSignature) -> Result<Signature, Box<dyn Error>> {
-    let timestamp_ms = SystemTime::now()
-        .duration_since(SystemTime::UNIX_EPOCH)?
-        .as_millis();
+pub fn get_signature_json(
+    signature: &DecodedSignature,
+    context: &RequestContext,
+) -> Result<Signature, Box<dyn Error>> {
+    let timestamp_ms = context.clock.now_ms()?;
     let samples =
         (signature.number_samples as f32 / signature.sample_rate_hz as f32 * 1000.) as u32;
     Ok(Signature {
-        geolocation: GeolocationResponse {
-            altitude: 300,
-            latitude: 45,
-            longitude: 2,
-        },
+        geolocation: context.geolocation.clone(),
         signature: SignatureSong {
             samples,
-            timestamp: timestamp_ms as u32,
+            samples_used: signature.samples_used,
+            timestamp: timestamp_ms,
             uri: signature.encode_to_uri()?,
         },
-        timestamp: timestamp_ms as u32,
-        timezone: "Europe/Paris".to_string(),
+        timestamp: timestamp_ms,
+        timezone: context.timezone.clone(),
+        quality: signature.quality.clone(),
     })
 }
+
+/// Query string the discovery endpoint expects on recognition requests.
+pub const DISCOVERY_QUERY: &str = "sync=true&webv3=true&sampling=true&connected=&shazamapiversion=v3&sharehub=true&hubv5minorversion=v5.1&hidelb=true&video=v3";
+
+/// Language, country and kind of device the discovery endpoint answers for.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RequestLocale {
+    /// Such as "en-US".
+    pub(crate) language: String,
+    /// Two-letter code of the country whose catalogue is searched, such as
+    /// "GB".
+    pub(crate) country: String,
+    /// "iphone", "android" or "web".
+    pub(crate) device: String,
+}
+
+impl Default for RequestLocale {
+    fn default() -> Self {
+        RequestLocale {
+            language: "en-US".to_string(),
+            country: "GB".to_string(),
+            device: "web".to_string(),
+        }
+    }
+}
+
+/// Signature as sent in a recognition request.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RequestSignature {
+    pub(crate) uri: String,
+    /// Duration of the audio the signature was made from, in milliseconds.
+    pub(crate) samplems: u32,
+    pub(crate) timestamp: u64,
+}
+
+/// Body of a recognition request. Its fields are serialised in a fixed
+/// order, so that every consumer sends the same bytes.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RequestBody {
+    pub(crate) geolocation: GeolocationResponse,
+    pub(crate) signature: RequestSignature,
+    pub(crate) timestamp: u64,
+    pub(crate) timezone: String,
+    /// Always empty, but expected by the endpoint.
+    pub(crate) context: serde_json::Map<String, serde_json::Value>,
+}
+
+/// A request recognizing a signature: the path of the discovery endpoint,
+/// relative to its base URL, and the JSON body to POST to it.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RecognitionRequest {
+    pub(crate) path: String,
+    pub(crate) body: RequestBody,
+}
+
+impl RecognitionRequest {
+    /// Builds the request for a signature. The path holds the identifiers
+    /// of the tag and of the installation making it, random UUIDs for
+    /// clients which do not keep them.
+    pub fn new(signature: &Signature, locale: &RequestLocale, tag_id: &str, installation_id: &str) -> Self {
+        RecognitionRequest {
+            path: format!(
+                "/discovery/v5/{}/{}/{}/-/tag/{}/{}?{}",
+                locale.language, locale.country, locale.device, tag_id, installation_id, DISCOVERY_QUERY
+            ),
+            body: RequestBody {
+                geolocation: signature.geolocation.clone(),
+                signature: RequestSignature {
+                    uri: signature.signature.uri.clone(),
+                    samplems: signature.signature.samples,
+                    timestamp: signature.signature.timestamp,
+                },
+                timestamp: signature.timestamp,
+                timezone: signature.timezone.clone(),
+                context: serde_json::Map::new(),
+            },
+        }
+    }
+}
+
+/// A random version 4 UUID, in upper case as the discovery endpoint
+/// expects in request paths.
+pub fn random_uuid() -> String {
+    // Version 4, variant 1
+    let bits = (fastrand::u128(..) & !(0xF << 76) & !(0x3 << 62)) | (0x4 << 76) | (0x2 << 62);
+    let hex = format!("{:032X}", bits);
+
+    format!(
+        "{}-{}-{}-{}-{}",
+        &hex[0..8],
+        &hex[8..12],
+        &hex[12..16],
+        &hex[16..20],
+        &hex[20..32]
+    )
+}
+
+/// Tells whether an identifier can be put in the path of a request as is.
+pub fn is_valid_request_id(id: &str) -> bool {
+    !id.is_empty() && id.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+}
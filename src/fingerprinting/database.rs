@@ -0,0 +1,691 @@
+use crate::fingerprinting::errors::FingerprintError;
+use crate::fingerprinting::landmark_index::{
+    best_match, cast_votes, landmarks_from_signature, Landmark, LandmarkIndex, LandmarkMatch,
+    Posting, Votes,
+};
+use crate::fingerprinting::signature_format::DecodedSignature;
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use crc32fast::Hasher;
+use memmap2::Mmap;
+use std::error::Error;
+use std::fs::{File, OpenOptions};
+use std::io::{BufWriter, Cursor, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+// On-disk layout of the compacted database file, all integers little-endian:
+//
+//   header        magic "SZFP", version u32, generation u64, track_count u32,
+//                 hash_count u32, posting_count u64, tracks_size u64
+//   tracks        track_count times (length u16, UTF-8 track ID)
+//   hashes        hash_count times (hash u32, posting_count u32, first_posting u64),
+//                 sorted by hash
+//   postings      posting_count times (track u32, fft_pass_number u32)
+//
+// Signatures added since the last compaction are appended to "<path>.log":
+// a header (magic "SZFL", version u32, generation u64) followed by records
+// of (track ID length u32, track ID, landmark count u32, landmarks as
+// (hash u32, fft_pass_number u32), CRC32 of the record u32). The log only
+// applies to the base file of the same generation.
+
+const DATABASE_MAGIC: &[u8; 4] = b"SZFP";
+const LOG_MAGIC: &[u8; 4] = b"SZFL";
+const FORMAT_VERSION: u32 = 1;
+
+const HEADER_SIZE: usize = 40;
+const LOG_HEADER_SIZE: u64 = 16;
+const HASH_ENTRY_SIZE: usize = 16;
+const POSTING_SIZE: usize = 8;
+
+struct BaseFile {
+    mmap: Mmap,
+    hash_count: usize,
+    hashes_offset: usize,
+    postings_offset: usize,
+}
+
+impl BaseFile {
+    fn open(path: &Path) -> Result<(BaseFile, u64, Vec<String>), Box<dyn Error>> {
+        let file = File::open(path)?;
+
+        // Safety: the database file is only ever replaced through a rename
+        // during compaction, never modified in place.
+        let mmap = unsafe { Mmap::map(&file)? };
+
+        if mmap.len() < HEADER_SIZE || &mmap[..4] != DATABASE_MAGIC {
+            return Err(Box::new(FingerprintError::CorruptDatabase(
+                "not a fingerprint database",
+            )));
+        }
+
+        let mut header = Cursor::new(&mmap[4..HEADER_SIZE]);
+        let version = header.read_u32::<LittleEndian>()?;
+        if version != FORMAT_VERSION {
+            return Err(Box::new(FingerprintError::UnsupportedDatabaseVersion(version)));
+        }
+
+        let generation = header.read_u64::<LittleEndian>()?;
+        let track_count = header.read_u32::<LittleEndian>()? as usize;
+        let hash_count = header.read_u32::<LittleEndian>()? as usize;
+        let posting_count = header.read_u64::<LittleEndian>()? as usize;
+        let tracks_size = header.read_u64::<LittleEndian>()? as usize;
+
+        // Sizes come from the file, so a corrupt header must not overflow them
+
+        let file_size = HEADER_SIZE
+            .checked_add(tracks_size)
+            .and_then(|hashes_offset| {
+                let postings_offset =
+                    hashes_offset.checked_add(hash_count.checked_mul(HASH_ENTRY_SIZE)?)?;
+                let file_size =
+                    postings_offset.checked_add(posting_count.checked_mul(POSTING_SIZE)?)?;
+                Some((hashes_offset, postings_offset, file_size))
+            });
+        let (hashes_offset, postings_offset) = match file_size {
+            Some((hashes_offset, postings_offset, file_size)) if file_size == mmap.len() => {
+                (hashes_offset, postings_offset)
+            }
+            _ => {
+                return Err(Box::new(FingerprintError::CorruptDatabase(
+                    "file size does not match its header",
+                )))
+            }
+        };
+
+        let mut tracks = Cursor::new(&mmap[HEADER_SIZE..hashes_offset]);
+        let mut track_ids = Vec::with_capacity(track_count);
+        for _ in 0..track_count {
+            let mut track_id = vec![0u8; tracks.read_u16::<LittleEndian>()? as usize];
+            tracks.read_exact(&mut track_id)?;
+            track_ids.push(String::from_utf8(track_id)?);
+        }
+
+        // Hash entries are only checked when they are used, so opening does
+        // not read the whole file
+
+        Ok((
+            BaseFile {
+                mmap,
+                hash_count,
+                hashes_offset,
+                postings_offset,
+            },
+            generation,
+            track_ids,
+        ))
+    }
+
+    fn hash_entry(&self, index: usize) -> (u32, u32, u64) {
+        let offset = self.hashes_offset + index * HASH_ENTRY_SIZE;
+        let entry = &self.mmap[offset..offset + HASH_ENTRY_SIZE];
+
+        (
+            u32::from_le_bytes(entry[0..4].try_into().unwrap()),
+            u32::from_le_bytes(entry[4..8].try_into().unwrap()),
+            u64::from_le_bytes(entry[8..16].try_into().unwrap()),
+        )
+    }
+
+    /// Returns the postings of a hash entry, which come from the file and
+    /// may point anywhere.
+    fn postings_range(
+        &self,
+        posting_count: u32,
+        first_posting: u64,
+    ) -> Result<&[u8], FingerprintError> {
+        usize::try_from(first_posting)
+            .ok()
+            .and_then(|first_posting| {
+                let start = self
+                    .postings_offset
+                    .checked_add(first_posting.checked_mul(POSTING_SIZE)?)?;
+                let end = start.checked_add((posting_count as usize).checked_mul(POSTING_SIZE)?)?;
+                self.mmap.get(start..end)
+            })
+            .ok_or(FingerprintError::CorruptDatabase(
+                "hash entry points past the postings",
+            ))
+    }
+
+    fn postings(
+        &self,
+        hash: u32,
+    ) -> Result<impl Iterator<Item = Posting> + '_, FingerprintError> {
+        let (mut low, mut high) = (0, self.hash_count);
+        let mut found = None;
+
+        while low < high {
+            let middle = (low + high) / 2;
+            let (entry_hash, posting_count, first_posting) = self.hash_entry(middle);
+
+            match entry_hash.cmp(&hash) {
+                std::cmp::Ordering::Less => low = middle + 1,
+                std::cmp::Ordering::Greater => high = middle,
+                std::cmp::Ordering::Equal => {
+                    found = Some(self.postings_range(posting_count, first_posting)?);
+                    break;
+                }
+            }
+        }
+
+        Ok(found
+            .unwrap_or_default()
+            .chunks_exact(POSTING_SIZE)
+            .map(|posting| Posting {
+                track: u32::from_le_bytes(posting[0..4].try_into().unwrap()),
+                fft_pass_number: u32::from_le_bytes(posting[4..8].try_into().unwrap()),
+            }))
+    }
+}
+
+/// Fingerprint catalogue persisted on disk. The compacted postings are
+/// memory-mapped so opening a database does not depend on its size, and new
+/// signatures are appended to a log until `compact` merges them in.
+pub struct FingerprintDatabase {
+    path: PathBuf,
+    base: Option<BaseFile>,
+    generation: u64,
+    pending: LandmarkIndex,
+    log: File,
+    /// Set when a failed append could not be rolled back, so the log may
+    /// end with a torn record which would hide any record appended after it,
+    /// or when a compaction could not replace the log.
+    log_poisoned: bool,
+}
+
+impl FingerprintDatabase {
+    /// Opens the database at `path`, creating it if it does not exist.
+    pub fn open(path: impl AsRef<Path>) -> Result<FingerprintDatabase, Box<dyn Error>> {
+        let path = path.as_ref().to_path_buf();
+
+        let (base, generation, base_track_ids) = if path.exists() {
+            let (base, generation, track_ids) = BaseFile::open(&path)?;
+            (Some(base), generation, track_ids)
+        } else {
+            (None, 0, vec![])
+        };
+
+        // Base tracks keep their numbers, so postings of both parts agree
+
+        let mut pending = LandmarkIndex::new();
+        for track_id in &base_track_ids {
+            pending.track_number(track_id);
+        }
+
+        let log = replay_log(&log_path(&path), generation, &mut pending)?;
+
+        Ok(FingerprintDatabase {
+            path,
+            base,
+            generation,
+            pending,
+            log,
+            log_poisoned: false,
+        })
+    }
+
+    pub fn track_count(&self) -> usize {
+        self.pending.track_count()
+    }
+
+    /// Durably appends the landmarks of a signature to the log.
+    pub fn add_signature(
+        &mut self,
+        track_id: &str,
+        signature: &DecodedSignature,
+    ) -> Result<(), Box<dyn Error>> {
+        if track_id.len() > u16::MAX as usize {
+            return Err("Track IDs are limited to 65535 bytes".into());
+        }
+        if self.log_poisoned {
+            return Err("The database log could not be updated after a failed write, reopen the database".into());
+        }
+
+        let landmarks = landmarks_from_signature(signature);
+
+        let mut record = vec![];
+        record.write_u32::<LittleEndian>(track_id.len() as u32)?;
+        record.write_all(track_id.as_bytes())?;
+        record.write_u32::<LittleEndian>(landmarks.len() as u32)?;
+        for landmark in &landmarks {
+            record.write_u32::<LittleEndian>(landmark.hash)?;
+            record.write_u32::<LittleEndian>(landmark.anchor_fft_pass_number)?;
+        }
+        let mut hasher = Hasher::new();
+        hasher.update(&record);
+        record.write_u32::<LittleEndian>(hasher.finalize())?;
+
+        let log_length = self.log.seek(SeekFrom::End(0))?;
+        if let Err(error) = self
+            .log
+            .write_all(&record)
+            .and_then(|_| self.log.sync_data())
+        {
+            // Drop what was written of the record, or refuse further appends
+            let rollback = self
+                .log
+                .set_len(log_length)
+                .and_then(|_| self.log.seek(SeekFrom::Start(log_length)));
+            if rollback.is_err() {
+                self.log_poisoned = true;
+            }
+            return Err(error.into());
+        }
+
+        self.pending.add_landmarks(track_id, &landmarks);
+        Ok(())
+    }
+
+    /// Finds the track matching a signature best. Fails when the postings
+    /// it looks up are corrupt.
+    pub fn query(
+        &self,
+        signature: &DecodedSignature,
+    ) -> Result<Option<LandmarkMatch>, Box<dyn Error>> {
+        let landmarks = landmarks_from_signature(signature);
+        let mut votes = Votes::new();
+
+        if let Some(base) = &self.base {
+            for landmark in &landmarks {
+                cast_votes(landmark, base.postings(landmark.hash)?, &mut votes);
+            }
+        }
+        self.pending.vote(&landmarks, &mut votes);
+
+        Ok(best_match(votes, self.pending.track_ids()))
+    }
+
+    /// Merges the log into a new database file, which atomically replaces
+    /// the current one, then starts a new empty log.
+    pub fn compact(&mut self) -> Result<(), Box<dyn Error>> {
+        let generation = self.generation + 1;
+        let temporary_path = path_with_suffix(&self.path, ".tmp");
+
+        // The new file is written aside, so the database is left as it was
+        // when that fails
+
+        if let Err(error) = self.write_base(&temporary_path, generation) {
+            let _ = std::fs::remove_file(&temporary_path);
+            return Err(error);
+        }
+
+        // Windows cannot replace a file which is still mapped, so the current
+        // one is mapped again when it could not be replaced
+
+        let had_base = self.base.take().is_some();
+        if let Err(error) = std::fs::rename(&temporary_path, &self.path) {
+            let _ = std::fs::remove_file(&temporary_path);
+            if had_base {
+                self.base = Some(BaseFile::open(&self.path)?.0);
+            }
+            return Err(error.into());
+        }
+
+        // The new file holds everything the log did, so it is switched to
+        // right away. The previous log no longer matches its generation, so
+        // a crash before it is recreated cannot replay it twice, and appends
+        // are refused until it is
+
+        self.log_poisoned = true;
+        let (base, _, track_ids) = BaseFile::open(&self.path)?;
+        self.base = Some(base);
+        self.generation = generation;
+
+        let mut pending = LandmarkIndex::new();
+        for track_id in &track_ids {
+            pending.track_number(track_id);
+        }
+        self.pending = pending;
+
+        sync_parent_directory(&self.path)?;
+        self.log = create_log(&log_path(&self.path), generation)?;
+        self.log_poisoned = false;
+
+        Ok(())
+    }
+
+    fn write_base(&self, path: &Path, generation: u64) -> Result<(), Box<dyn Error>> {
+        let mut pending_hashes: Vec<u32> = self.pending.postings().keys().copied().collect();
+        pending_hashes.sort_unstable();
+
+        let base_hash_count = self.base.as_ref().map_or(0, |base| base.hash_count);
+
+        // Both hash lists are sorted, merge them into (hash, base entry, pending entry)
+
+        let mut merged = vec![];
+        let (mut base_index, mut pending_index) = (0, 0);
+        while base_index < base_hash_count || pending_index < pending_hashes.len() {
+            let base_hash = self
+                .base
+                .as_ref()
+                .filter(|_| base_index < base_hash_count)
+                .map(|base| base.hash_entry(base_index).0);
+            let pending_hash = pending_hashes.get(pending_index).copied();
+
+            match (base_hash, pending_hash) {
+                (Some(a), Some(b)) if a == b => {
+                    merged.push((a, Some(base_index), Some(b)));
+                    base_index += 1;
+                    pending_index += 1;
+                }
+                (Some(a), Some(b)) if a < b => {
+                    merged.push((a, Some(base_index), None));
+                    base_index += 1;
+                }
+                (Some(a), None) => {
+                    merged.push((a, Some(base_index), None));
+                    base_index += 1;
+                }
+                (_, Some(b)) => {
+                    merged.push((b, None, Some(b)));
+                    pending_index += 1;
+                }
+                (None, None) => break,
+            }
+        }
+
+        let mut writer = BufWriter::new(File::create(path)?);
+
+        let mut tracks = vec![];
+        for track_id in self.pending.track_ids() {
+            tracks.write_u16::<LittleEndian>(track_id.len() as u16)?;
+            tracks.write_all(track_id.as_bytes())?;
+        }
+
+        let posting_counts = merged
+            .iter()
+            .map(|(_, base_entry, pending_hash)| {
+                let base_count = base_entry.map_or(0, |index| {
+                    self.base.as_ref().unwrap().hash_entry(index).1
+                });
+                let pending_count = pending_hash.map_or(0, |hash| self.pending.postings()[&hash].len());
+                u32::try_from(pending_count)
+                    .ok()
+                    .and_then(|pending_count| base_count.checked_add(pending_count))
+                    .ok_or("A hash has more than 2^32 postings")
+            })
+            .collect::<Result<Vec<u32>, _>>()?;
+        let posting_count: u64 = posting_counts.iter().map(|count| *count as u64).sum();
+
+        writer.write_all(DATABASE_MAGIC)?;
+        writer.write_u32::<LittleEndian>(FORMAT_VERSION)?;
+        writer.write_u64::<LittleEndian>(generation)?;
+        writer.write_u32::<LittleEndian>(self.pending.track_count() as u32)?;
+        writer.write_u32::<LittleEndian>(merged.len() as u32)?;
+        writer.write_u64::<LittleEndian>(posting_count)?;
+        writer.write_u64::<LittleEndian>(tracks.len() as u64)?;
+        writer.write_all(&tracks)?;
+
+        let mut first_posting = 0u64;
+        for ((hash, _, _), count) in merged.iter().zip(&posting_counts) {
+            writer.write_u32::<LittleEndian>(*hash)?;
+            writer.write_u32::<LittleEndian>(*count)?;
+            writer.write_u64::<LittleEndian>(first_posting)?;
+            first_posting += *count as u64;
+        }
+
+        for (_, base_entry, pending_hash) in &merged {
+            if let (Some(index), Some(base)) = (base_entry, &self.base) {
+                let (_, count, first) = base.hash_entry(*index);
+                writer.write_all(base.postings_range(count, first)?)?;
+            }
+            if let Some(hash) = pending_hash {
+                for posting in &self.pending.postings()[hash] {
+                    writer.write_u32::<LittleEndian>(posting.track)?;
+                    writer.write_u32::<LittleEndian>(posting.fft_pass_number)?;
+                }
+            }
+        }
+
+        writer.into_inner()?.sync_all()?;
+        Ok(())
+    }
+}
+
+fn path_with_suffix(path: &Path, suffix: &str) -> PathBuf {
+    let mut path = path.as_os_str().to_owned();
+    path.push(suffix);
+    PathBuf::from(path)
+}
+
+fn log_path(path: &Path) -> PathBuf {
+    path_with_suffix(path, ".log")
+}
+
+/// Replaces the log at `path` with an empty one. It is written aside and
+/// renamed over the log, so a crash leaves either the previous log or the
+/// new one, never a truncated header.
+fn create_log(path: &Path, generation: u64) -> Result<File, Box<dyn Error>> {
+    let temporary_path = path_with_suffix(path, ".tmp");
+
+    let mut log = File::create(&temporary_path)?;
+    log.write_all(LOG_MAGIC)?;
+    log.write_u32::<LittleEndian>(FORMAT_VERSION)?;
+    log.write_u64::<LittleEndian>(generation)?;
+    log.sync_all()?;
+
+    std::fs::rename(&temporary_path, path)?;
+    sync_parent_directory(path)?;
+    Ok(log)
+}
+
+/// Loads the log records into `pending` and returns the log opened for
+/// appending. A log left over from an older generation, or a record torn
+/// by a crash, is discarded.
+fn replay_log(
+    path: &Path,
+    generation: u64,
+    pending: &mut LandmarkIndex,
+) -> Result<File, Box<dyn Error>> {
+    if !path.exists() {
+        return create_log(path, generation);
+    }
+
+    let mut contents = vec![];
+    File::open(path)?.read_to_end(&mut contents)?;
+
+    let mut header = Cursor::new(&contents[..]);
+    let mut magic = [0u8; 4];
+    if header.read_exact(&mut magic).is_err() || &magic != LOG_MAGIC {
+        return Err(Box::new(FingerprintError::CorruptDatabase(
+            "not a fingerprint database log",
+        )));
+    }
+    let version = header.read_u32::<LittleEndian>()?;
+    if version != FORMAT_VERSION {
+        return Err(Box::new(FingerprintError::UnsupportedDatabaseVersion(version)));
+    }
+    if header.read_u64::<LittleEndian>()? != generation {
+        return create_log(path, generation);
+    }
+
+    let mut valid_length = LOG_HEADER_SIZE;
+    while let Some((track_id, landmarks, length)) = read_log_record(&contents[valid_length as usize..]) {
+        pending.add_landmarks(&track_id, &landmarks);
+        valid_length += length;
+    }
+
+    let mut log = OpenOptions::new().write(true).open(path)?;
+    log.set_len(valid_length)?;
+    log.seek(SeekFrom::End(0))?;
+    Ok(log)
+}
+
+fn read_log_record(data: &[u8]) -> Option<(String, Vec<Landmark>, u64)> {
+    let mut cursor = Cursor::new(data);
+
+    let track_id_length = cursor.read_u32::<LittleEndian>().ok()? as usize;
+    if track_id_length > data.len() - cursor.position() as usize {
+        return None;
+    }
+    let mut track_id = vec![0u8; track_id_length];
+    cursor.read_exact(&mut track_id).ok()?;
+
+    let landmark_count = cursor.read_u32::<LittleEndian>().ok()? as usize;
+    let mut landmarks = Vec::with_capacity(landmark_count.min(data.len() / 8));
+    for _ in 0..landmark_count {
+        landmarks.push(Landmark {
+            hash: cursor.read_u32::<LittleEndian>().ok()?,
+            anchor_fft_pass_number: cursor.read_u32::<LittleEndian>().ok()?,
+        });
+    }
+
+    let record_length = cursor.position() as usize;
+    let crc32 = cursor.read_u32::<LittleEndian>().ok()?;
+
+    let mut hasher = Hasher::new();
+    hasher.update(&data[..record_length]);
+    if hasher.finalize() != crc32 {
+        return None;
+    }
+
+    Some((
+        String::from_utf8(track_id).ok()?,
+        landmarks,
+        record_length as u64 + 4,
+    ))
+}
+
+fn sync_parent_directory(path: &Path) -> Result<(), Box<dyn Error>> {
+    #[cfg(unix)]
+    if let Some(parent) = path.parent().filter(|parent| !parent.as_os_str().is_empty()) {
+        File::open(parent)?.sync_all()?;
+    }
+    #[cfg(not(unix))]
+    let _ = path;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fingerprinting::signature_format::{FrequencyBand, FrequencyPeak};
+    use std::collections::HashMap;
+
+    /// Path of a database in a new empty directory, removed by the caller.
+    fn database_path(name: &str) -> PathBuf {
+        let directory = std::env::temp_dir().join(format!(
+            "shazamio-core-{}-{}-{}",
+            name,
+            std::process::id(),
+            fastrand::u64(..)
+        ));
+        std::fs::create_dir_all(&directory).unwrap();
+        directory.join("fingerprints.db")
+    }
+
+    fn signature(first_bin: u16) -> DecodedSignature {
+        let peaks = (0..20)
+            .map(|index| FrequencyPeak {
+                fft_pass_number: index * 7,
+                peak_magnitude: 7000,
+                corrected_peak_frequency_bin: first_bin + (index as u16 % 5) * 640,
+            })
+            .collect();
+
+        DecodedSignature {
+            sample_rate_hz: 16000,
+            number_samples: 48000,
+            samples_used: 48000,
+            frequency_band_to_sound_peaks: HashMap::from([(FrequencyBand::_520_1450, peaks)]),
+            quality: None,
+        }
+    }
+
+    fn compacted_database(path: &Path) -> Vec<u8> {
+        let mut database = FingerprintDatabase::open(path).unwrap();
+        database.add_signature("first", &signature(9000)).unwrap();
+        database.add_signature("second", &signature(20000)).unwrap();
+        database.compact().unwrap();
+        std::fs::read(path).unwrap()
+    }
+
+    fn corrupt_reason<T>(result: Result<T, Box<dyn Error>>) -> &'static str {
+        match result {
+            Err(error) => match error.downcast_ref::<FingerprintError>() {
+                Some(FingerprintError::CorruptDatabase(reason)) => reason,
+                _ => panic!("expected a corrupt database, got {}", error),
+            },
+            Ok(_) => panic!("corrupt database was used"),
+        }
+    }
+
+    #[test]
+    fn compacted_database_is_reopened() {
+        let path = database_path("reopen");
+        compacted_database(&path);
+        // A log left aside by a crash while it was replaced is ignored
+        std::fs::write(path_with_suffix(&log_path(&path), ".tmp"), b"SZ").unwrap();
+
+        let mut database = FingerprintDatabase::open(&path).unwrap();
+        database.add_signature("third", &signature(30000)).unwrap();
+        let found = database.query(&signature(20000)).unwrap().unwrap();
+        assert_eq!(found.track_id, "second");
+        assert_eq!(database.track_count(), 3);
+
+        std::fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn rejects_overflowing_header_sizes() {
+        let path = database_path("overflow");
+        let mut data = compacted_database(&path);
+
+        data[32..40].copy_from_slice(&u64::MAX.to_le_bytes());
+        std::fs::write(&path, &data).unwrap();
+        assert_eq!(
+            corrupt_reason(FingerprintDatabase::open(&path)),
+            "file size does not match its header"
+        );
+
+        std::fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn rejects_hash_entries_past_the_postings_when_used() {
+        let path = database_path("postings");
+        let mut data = compacted_database(&path);
+
+        // Point every hash entry past the postings, or past any address
+        let hash_count = u32::from_le_bytes(data[20..24].try_into().unwrap()) as usize;
+        let posting_count = u64::from_le_bytes(data[24..32].try_into().unwrap());
+        let tracks_size = u64::from_le_bytes(data[32..40].try_into().unwrap()) as usize;
+        for index in 0..hash_count {
+            let first_posting = HEADER_SIZE + tracks_size + index * HASH_ENTRY_SIZE + 8;
+            let corrupt_posting = if index % 2 == 0 { posting_count } else { u64::MAX };
+            data[first_posting..first_posting + 8].copy_from_slice(&corrupt_posting.to_le_bytes());
+        }
+        std::fs::write(&path, &data).unwrap();
+
+        // Opening does not look at the entries, queries and compaction do
+        let mut database = FingerprintDatabase::open(&path).unwrap();
+        assert_eq!(
+            corrupt_reason(database.query(&signature(20000))),
+            "hash entry points past the postings"
+        );
+        assert_eq!(corrupt_reason(database.compact()), "hash entry points past the postings");
+        assert_eq!(std::fs::read(&path).unwrap(), data);
+        assert!(!path_with_suffix(&path, ".tmp").exists());
+
+        std::fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn discards_log_records_longer_than_the_log() {
+        let path = database_path("torn");
+        let mut database = FingerprintDatabase::open(&path).unwrap();
+        database.add_signature("first", &signature(9000)).unwrap();
+        drop(database);
+
+        let log_length = std::fs::metadata(log_path(&path)).unwrap().len();
+        let mut log = OpenOptions::new().append(true).open(log_path(&path)).unwrap();
+        log.write_u32::<LittleEndian>(u32::MAX).unwrap();
+        log.write_all(b"torn").unwrap();
+        drop(log);
+
+        let database = FingerprintDatabase::open(&path).unwrap();
+        assert_eq!(database.track_count(), 1);
+        assert_eq!(std::fs::metadata(log_path(&path)).unwrap().len(), log_length);
+
+        std::fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+}
@@ -0,0 +1,524 @@
+use crate::fingerprinting::audio_format::{sniff_format, AudioFormat, SNIFFED_BYTES};
+use crate::fingerprinting::audio_stream::StreamSelector;
+use crate::fingerprinting::errors::FingerprintError;
+use crate::fingerprinting::ffmpeg_wrapper::{
+    decode_with_ffmpeg, decode_with_ffmpeg_from_bytes, FFMpegSettings,
+};
+use crate::fingerprinting::shared_bytes::SharedBytes;
+#[cfg(feature = "symphonia")]
+use crate::fingerprinting::symphonia_decoder::decode_with_symphonia;
+use log::debug;
+use std::error::Error;
+use std::fmt;
+use std::io::{BufReader, Cursor, Read};
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+
+/// Audio to decode, either a file or its content, shared by the decoders.
+#[derive(Debug, Clone, Copy)]
+pub enum AudioInput<'a> {
+    File(&'a str),
+    Bytes(&'a SharedBytes),
+}
+
+impl AudioInput<'_> {
+    /// Extension of the file, a hint for the decoders which need one.
+    pub fn extension(&self) -> Option<&str> {
+        match self {
+            AudioInput::File(file_path) => std::path::Path::new(file_path)
+                .extension()
+                .and_then(|extension| extension.to_str()),
+            AudioInput::Bytes(_) => None,
+        }
+    }
+
+    /// Recognizes the format of the input from its first bytes.
+    pub fn sniff_format(&self) -> std::io::Result<Option<AudioFormat>> {
+        match self {
+            AudioInput::File(file_path) => {
+                let mut header = Vec::with_capacity(SNIFFED_BYTES);
+                std::fs::File::open(file_path)?
+                    .take(SNIFFED_BYTES as u64)
+                    .read_to_end(&mut header)?;
+                Ok(sniff_format(&header))
+            }
+            AudioInput::Bytes(bytes) => Ok(sniff_format(bytes.as_ref())),
+        }
+    }
+}
+
+/// What is needed from the input, besides its samples.
+#[derive(Debug, Clone, Copy)]
+pub struct DecodeOptions<'a> {
+    /// Only the segment of this duration in the middle of the input is
+    /// needed. Decoders which know the duration of their input can decode
+    /// only that segment, others decode everything.
+    pub segment: Option<Duration>,
+    /// The audio stream to decode, in containers holding several of them.
+    pub stream: Option<&'a StreamSelector>,
+    /// Settings for the decoders running FFMpeg, including the flag set
+    /// when the task is cancelled.
+    pub ffmpeg: &'a FFMpegSettings,
+    /// Duration of the input, when measured by decoding it once already,
+    /// for decoders which cannot tell it to seek to the segment.
+    pub duration: Option<Duration>,
+}
+
+/// Number of samples decoders read from Rodio sources at a time.
+const CHUNK_SAMPLES: usize = 16384;
+
+/// Chunks of interleaved samples, produced as the input is decoded. An
+/// error ends the audio.
+pub type PcmChunks = Box<dyn Iterator<Item = Result<Vec<i16>, Box<dyn Error>>>>;
+
+/// Interleaved 16-bit PCM samples, at any sample rate and number of
+/// channels, read chunk by chunk while they are decoded. They are downmixed,
+/// resampled to 16 KHz mono and fingerprinted as they come, so that the
+/// decoded input is never held in memory as a whole.
+pub struct DecodedAudio {
+    pub sample_rate: u32,
+    pub channels: u16,
+    /// Duration of the samples, when known. The middle segment of audio of
+    /// unknown duration is found by decoding it twice.
+    pub duration: Option<Duration>,
+    pub chunks: PcmChunks,
+}
+
+impl DecodedAudio {
+    /// Audio decoded chunk by chunk, of unknown duration.
+    pub fn new<I>(sample_rate: u32, channels: u16, chunks: I) -> Self
+    where
+        I: Iterator<Item = Result<Vec<i16>, Box<dyn Error>>> + 'static,
+    {
+        DecodedAudio {
+            sample_rate,
+            channels,
+            duration: None,
+            chunks: Box::new(chunks),
+        }
+    }
+
+    /// Samples decoded all at once.
+    pub fn from_samples(samples: Vec<i16>, sample_rate: u32, channels: u16) -> Self {
+        let frames = samples.len() as f64 / channels.max(1) as f64;
+
+        DecodedAudio {
+            duration: Some(Duration::from_secs_f64(frames / sample_rate.max(1) as f64)),
+            ..DecodedAudio::new(sample_rate, channels, std::iter::once(Ok(samples)))
+        }
+    }
+
+    /// Samples already downmixed and resampled to 16 KHz mono.
+    pub fn from_16khz_mono(samples: Vec<i16>) -> Self {
+        DecodedAudio::from_samples(samples, 16000, 1)
+    }
+
+    /// Samples read from a Rodio source, such as a decoder, as they are
+    /// needed.
+    pub fn from_source<S>(source: S, duration: Option<Duration>) -> Self
+    where
+        S: rodio::Source<Item = i16> + 'static,
+    {
+        let (sample_rate, channels) = (source.sample_rate(), source.channels());
+
+        DecodedAudio {
+            duration,
+            ..DecodedAudio::new(sample_rate, channels, chunks_of(source))
+        }
+    }
+}
+
+/// Splits samples read one at a time into chunks.
+fn chunks_of<I>(mut samples: I) -> impl Iterator<Item = Result<Vec<i16>, Box<dyn Error>>>
+where
+    I: Iterator<Item = i16>,
+{
+    std::iter::from_fn(move || {
+        let chunk: Vec<i16> = samples.by_ref().take(CHUNK_SAMPLES).collect();
+        (!chunk.is_empty()).then_some(Ok(chunk))
+    })
+}
+
+impl fmt::Debug for DecodedAudio {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("DecodedAudio")
+            .field("sample_rate", &self.sample_rate)
+            .field("channels", &self.channels)
+            .field("duration", &self.duration)
+            .finish_non_exhaustive()
+    }
+}
+
+/// A decoder turning some audio formats into PCM samples. Implement it to
+/// decode formats the built-in decoders do not support, and add it to a
+/// `DecoderRegistry`.
+pub trait AudioDecoder: Send + Sync {
+    /// Name of the decoder, used in logs and to reorder a registry.
+    fn name(&self) -> &str;
+
+    /// Tells whether the decoder handles the format of an input, `None`
+    /// when it was not recognized. Decoders which do not support a format
+    /// are not tried on it.
+    fn supports(&self, format: Option<AudioFormat>) -> bool;
+
+    /// Opens the input and returns its samples, preferably decoded lazily
+    /// as the chunks are read. Errors found while opening it are returned
+    /// here, so that the next decoder can be tried.
+    fn decode(&self, input: AudioInput, options: &DecodeOptions) -> Result<DecodedAudio, Box<dyn Error>>;
+}
+
+/// Decodes .WAV, .MP3, .OGG (Vorbis) and .FLAC with Rodio.
+pub struct RodioDecoder;
+
+impl RodioDecoder {
+    fn decode_source<S>(mut source: S, options: &DecodeOptions) -> DecodedAudio
+    where
+        S: rodio::Source<Item = i16> + 'static,
+    {
+        let duration = source.total_duration().or(options.duration);
+
+        // Otherwise what comes before the segment is decoded and dropped
+        if let (Some(segment), Some(duration)) = (options.segment, duration) {
+            if duration > segment && source.try_seek((duration - segment) / 2).is_ok() {
+                let (sample_rate, channels) = (source.sample_rate(), source.channels());
+                let segment_frames = (segment.as_secs_f64() * sample_rate as f64) as usize;
+
+                return DecodedAudio {
+                    duration: Some(segment),
+                    ..DecodedAudio::new(
+                        sample_rate,
+                        channels,
+                        chunks_of(source.take(segment_frames * channels as usize)),
+                    )
+                };
+            }
+        }
+
+        DecodedAudio::from_source(source, duration)
+    }
+}
+
+impl AudioDecoder for RodioDecoder {
+    fn name(&self) -> &str {
+        "rodio"
+    }
+
+    fn supports(&self, format: Option<AudioFormat>) -> bool {
+        matches!(
+            format,
+            None | Some(AudioFormat::Wav | AudioFormat::Flac | AudioFormat::Ogg | AudioFormat::Mp3)
+        )
+    }
+
+    fn decode(&self, input: AudioInput, options: &DecodeOptions) -> Result<DecodedAudio, Box<dyn Error>> {
+        // The formats Rodio supports hold a single audio stream
+        if !matches!(options.stream, None | Some(StreamSelector::Index(0))) {
+            return Err("Selecting an audio stream other than the first one needs Symphonia or FFmpeg".into());
+        }
+
+        match input {
+            AudioInput::File(file_path) => {
+                let decoder = rodio::Decoder::new(BufReader::new(std::fs::File::open(file_path)?))?;
+                Ok(RodioDecoder::decode_source(decoder, options))
+            }
+            AudioInput::Bytes(bytes) => {
+                let decoder = rodio::Decoder::new(Cursor::new(bytes.clone()))?;
+                Ok(RodioDecoder::decode_source(decoder, options))
+            }
+        }
+    }
+}
+
+/// Decodes in pure Rust with Symphonia, including the formats Rodio can't
+/// handle (MP4/M4A with AAC or ALAC, MKV/WebM). Symphonia has no Opus
+/// decoder, so Ogg Opus is left to FFMpeg, and so are Opus tracks of
+/// MKV/WebM files, which fail here.
+#[cfg(feature = "symphonia")]
+pub struct SymphoniaDecoder;
+
+#[cfg(feature = "symphonia")]
+impl AudioDecoder for SymphoniaDecoder {
+    fn name(&self) -> &str {
+        "symphonia"
+    }
+
+    fn supports(&self, format: Option<AudioFormat>) -> bool {
+        matches!(
+            format,
+            None | Some(
+                AudioFormat::Wav
+                    | AudioFormat::Flac
+                    | AudioFormat::Ogg
+                    | AudioFormat::Aac
+                    | AudioFormat::Mp4
+                    | AudioFormat::Matroska
+            )
+        )
+    }
+
+    fn decode(&self, input: AudioInput, options: &DecodeOptions) -> Result<DecodedAudio, Box<dyn Error>> {
+        let source: Box<dyn symphonia::core::io::MediaSource> = match input {
+            AudioInput::File(file_path) => Box::new(std::fs::File::open(file_path)?),
+            AudioInput::Bytes(bytes) => Box::new(Cursor::new(bytes.clone())),
+        };
+
+        decode_with_symphonia(source, input.extension(), options)
+    }
+}
+
+/// Decodes any format with FFMpeg, when it is installed.
+pub struct FFMpegDecoder;
+
+impl AudioDecoder for FFMpegDecoder {
+    fn name(&self) -> &str {
+        "ffmpeg"
+    }
+
+    fn supports(&self, _format: Option<AudioFormat>) -> bool {
+        true
+    }
+
+    fn decode(&self, input: AudioInput, options: &DecodeOptions) -> Result<DecodedAudio, Box<dyn Error>> {
+        match input {
+            AudioInput::File(file_path) => decode_with_ffmpeg(file_path, options),
+            AudioInput::Bytes(bytes) => decode_with_ffmpeg_from_bytes(bytes.clone(), options),
+        }
+    }
+}
+
+/// Registry recognizers start from, when replaced.
+static GLOBAL_REGISTRY: RwLock<Option<DecoderRegistry>> = RwLock::new(None);
+
+/// The decoders to try, in order. Only those supporting the format sniffed
+/// from the input are tried: the first of them decodes it, and the next
+/// ones are only tried when it fails to open the input, see `decode`.
+#[derive(Clone)]
+pub struct DecoderRegistry {
+    decoders: Vec<Arc<dyn AudioDecoder>>,
+}
+
+impl Default for DecoderRegistry {
+    /// Rodio, then Symphonia when enabled, then FFMpeg.
+    fn default() -> Self {
+        let mut registry = DecoderRegistry::empty();
+        registry.register(Arc::new(RodioDecoder));
+        #[cfg(feature = "symphonia")]
+        registry.register(Arc::new(SymphoniaDecoder));
+        registry.register(Arc::new(FFMpegDecoder));
+        registry
+    }
+}
+
+impl DecoderRegistry {
+    /// The registry recognizers created from now on start from: the
+    /// built-in decoders, unless replaced with `set_global`.
+    pub fn global() -> Self {
+        match GLOBAL_REGISTRY.read() {
+            Ok(registry) => registry.clone().unwrap_or_default(),
+            Err(poisoned) => poisoned.into_inner().clone().unwrap_or_default(),
+        }
+    }
+
+    /// Replaces the registry recognizers start from, e.g. to plug in a
+    /// decoder for a proprietary codec when initializing an application.
+    pub fn set_global(registry: DecoderRegistry) {
+        match GLOBAL_REGISTRY.write() {
+            Ok(mut global) => *global = Some(registry),
+            Err(poisoned) => *poisoned.into_inner() = Some(registry),
+        }
+    }
+
+    /// A registry without any decoder.
+    pub fn empty() -> Self {
+        DecoderRegistry { decoders: vec![] }
+    }
+
+    /// Adds a decoder, tried after the others.
+    pub fn register(&mut self, decoder: Arc<dyn AudioDecoder>) {
+        self.decoders.push(decoder);
+    }
+
+    /// Adds a decoder at the given position, 0 to try it first.
+    pub fn insert(&mut self, position: usize, decoder: Arc<dyn AudioDecoder>) {
+        self.decoders.insert(position.min(self.decoders.len()), decoder);
+    }
+
+    /// Removes the decoder with the given name, and returns it.
+    pub fn remove(&mut self, name: &str) -> Option<Arc<dyn AudioDecoder>> {
+        let position = self.decoders.iter().position(|decoder| decoder.name() == name)?;
+        Some(self.decoders.remove(position))
+    }
+
+    /// Moves the named decoders first, in the given order. The others are
+    /// tried after them, in their current order.
+    pub fn set_order(&mut self, names: &[&str]) -> Result<(), FingerprintError> {
+        let mut ordered = vec![];
+        for name in names {
+            ordered.push(
+                self.remove(name)
+                    .ok_or_else(|| FingerprintError::UnknownDecoder(name.to_string()))?,
+            );
+        }
+        ordered.append(&mut self.decoders);
+        self.decoders = ordered;
+        Ok(())
+    }
+
+    /// Names of the decoders, in the order they are tried.
+    pub fn names(&self) -> Vec<&str> {
+        self.decoders.iter().map(|decoder| decoder.name()).collect()
+    }
+
+    /// Decodes the input with the first decoder supporting its format. When
+    /// it fails to open the input, e.g. an MP4 file holding a codec it does
+    /// not know, the next decoder supporting the format is tried, and so on.
+    /// When they all fail, the error of the last one which could run is
+    /// returned, as FFMpeg, tried last by default, explains failures best.
+    ///
+    /// FFMpeg exceeding its limits or being cancelled is not a decoding
+    /// failure, and is returned without trying other decoders. Errors once
+    /// the first samples were decoded end the audio, without any fallback.
+    pub fn decode(&self, input: AudioInput, options: &DecodeOptions) -> Result<DecodedAudio, Box<dyn Error>> {
+        let format = input.sniff_format()?;
+        debug!("Sniffed audio format: {:?}", format);
+
+        let mut decoding_error: Option<Box<dyn Error>> = None;
+
+        for decoder in self.decoders.iter().filter(|decoder| decoder.supports(format)) {
+            match decoder.decode(input, options) {
+                Ok(audio) => return Ok(audio),
+                Err(error) => {
+                    debug!("{} failed to decode the input: {}", decoder.name(), error);
+
+                    match error.downcast_ref::<FingerprintError>() {
+                        // Keep the error of a decoder which could run
+                        Some(FingerprintError::FFMpegNotFound) if decoding_error.is_some() => {}
+                        Some(
+                            FingerprintError::FFMpegCancelled
+                            | FingerprintError::FFMpegTimedOut { .. }
+                            | FingerprintError::FFMpegStalled { .. }
+                            | FingerprintError::FFMpegOutputTooLarge { .. },
+                        ) => return Err(error),
+                        _ => decoding_error = Some(error),
+                    }
+                }
+            }
+        }
+
+        let error = decoding_error.unwrap_or_else(|| FingerprintError::UnsupportedFormat(format).into());
+
+        // Only FFMpeg decodes Opus, say so rather than just that it is missing
+        if format == Some(AudioFormat::Opus)
+            && matches!(
+                error.downcast_ref::<FingerprintError>(),
+                Some(FingerprintError::FFMpegNotFound | FingerprintError::UnsupportedFormat(_))
+            )
+        {
+            return Err(FingerprintError::UnsupportedCodec("Opus").into());
+        }
+
+        Err(error)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    /// Counts its attempts, and fails with the given error if any.
+    struct TestDecoder {
+        name: &'static str,
+        error: Option<FingerprintError>,
+        attempts: AtomicUsize,
+    }
+
+    impl TestDecoder {
+        fn new(name: &'static str, error: Option<FingerprintError>) -> Arc<Self> {
+            Arc::new(TestDecoder {
+                name,
+                error,
+                attempts: AtomicUsize::new(0),
+            })
+        }
+    }
+
+    impl AudioDecoder for TestDecoder {
+        fn name(&self) -> &str {
+            self.name
+        }
+
+        fn supports(&self, _format: Option<AudioFormat>) -> bool {
+            true
+        }
+
+        fn decode(&self, _input: AudioInput, _options: &DecodeOptions) -> Result<DecodedAudio, Box<dyn Error>> {
+            self.attempts.fetch_add(1, Ordering::Relaxed);
+            match &self.error {
+                Some(error) => Err(error.clone().into()),
+                None => Ok(DecodedAudio::from_samples(vec![0; 16], 16000, 1)),
+            }
+        }
+    }
+
+    fn decode_with(decoders: &[Arc<TestDecoder>]) -> Result<DecodedAudio, Box<dyn Error>> {
+        decode_bytes_with(decoders, b"RIFF\0\0\0\0WAVE".to_vec())
+    }
+
+    fn decode_bytes_with(decoders: &[Arc<TestDecoder>], bytes: Vec<u8>) -> Result<DecodedAudio, Box<dyn Error>> {
+        let mut registry = DecoderRegistry::empty();
+        for decoder in decoders {
+            registry.register(decoder.clone());
+        }
+
+        let bytes = SharedBytes::from(bytes);
+        let ffmpeg = FFMpegSettings::default();
+        let options = DecodeOptions {
+            segment: None,
+            stream: None,
+            ffmpeg: &ffmpeg,
+            duration: None,
+        };
+        registry.decode(AudioInput::Bytes(&bytes), &options)
+    }
+
+    #[test]
+    fn falls_back_only_when_a_decoder_fails() {
+        let failing = TestDecoder::new("failing", Some(FingerprintError::InvalidPcm("unknown codec".into())));
+        let working = TestDecoder::new("working", None);
+        let last = TestDecoder::new("last", None);
+
+        assert!(decode_with(&[failing.clone(), working.clone(), last.clone()]).is_ok());
+        assert_eq!(failing.attempts.load(Ordering::Relaxed), 1);
+        assert_eq!(working.attempts.load(Ordering::Relaxed), 1);
+        assert_eq!(last.attempts.load(Ordering::Relaxed), 0);
+    }
+
+    #[test]
+    fn returns_ffmpeg_limits_without_falling_back() {
+        let timeout = Duration::from_secs(1);
+        let timed_out = TestDecoder::new("timed-out", Some(FingerprintError::FFMpegTimedOut { timeout }));
+        let working = TestDecoder::new("working", None);
+
+        let error = decode_with(&[timed_out, working.clone()]).err().unwrap();
+        assert_eq!(
+            error.downcast_ref::<FingerprintError>(),
+            Some(&FingerprintError::FFMpegTimedOut { timeout })
+        );
+        assert_eq!(working.attempts.load(Ordering::Relaxed), 0);
+    }
+
+    #[test]
+    fn reports_opus_as_unsupported_without_ffmpeg() {
+        let mut opus = b"OggS".to_vec();
+        opus.resize(28, 0);
+        opus.extend_from_slice(b"OpusHead");
+        let missing = TestDecoder::new("missing", Some(FingerprintError::FFMpegNotFound));
+
+        let error = decode_bytes_with(&[missing], opus).err().unwrap();
+        assert_eq!(
+            error.downcast_ref::<FingerprintError>(),
+            Some(&FingerprintError::UnsupportedCodec("Opus"))
+        );
+    }
+}
@@ -0,0 +1,206 @@
+use crate::fingerprinting::landmark_index::{landmarks_from_signature, LandmarkIndex, Votes};
+use crate::fingerprinting::signature_format::DecodedSignature;
+use std::collections::HashMap;
+
+/// Fewer time-aligned landmarks than this are treated as chance collisions,
+/// whatever the similarity ratio.
+const MIN_ALIGNED_LANDMARKS: u32 = 20;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct DuplicateGroup {
+    /// Indexes of the grouped signatures, in input order.
+    pub members: Vec<usize>,
+    /// Weakest pairwise similarity which linked the group together, from 0
+    /// to 1.
+    pub similarity: f32,
+}
+
+fn find_root(parents: &mut [usize], mut node: usize) -> usize {
+    while parents[node] != node {
+        parents[node] = parents[parents[node]];
+        node = parents[node];
+    }
+    node
+}
+
+/// Clusters signatures of the same recording. Two signatures are linked
+/// when the share of their landmarks agreeing on a single time offset
+/// reaches `min_similarity`, which holds across re-encodes and bitrates
+/// unlike file hashes.
+pub fn find_duplicate_groups(
+    signatures: &[DecodedSignature],
+    min_similarity: f32,
+) -> Vec<DuplicateGroup> {
+    let landmarks: Vec<_> = signatures.iter().map(landmarks_from_signature).collect();
+
+    let mut index = LandmarkIndex::new();
+    for (number, signature_landmarks) in landmarks.iter().enumerate() {
+        index.add_landmarks(&number.to_string(), signature_landmarks);
+    }
+
+    let mut parents: Vec<usize> = (0..signatures.len()).collect();
+    let mut link_similarities: HashMap<usize, f32> = HashMap::new();
+    let mut edges = vec![];
+
+    for (query, query_landmarks) in landmarks.iter().enumerate() {
+        let mut votes = Votes::new();
+        index.vote(query_landmarks, &mut votes);
+
+        // Keep the best aligned offset of every other signature. Re-encodes
+        // rarely start on the same 128-sample frame boundary, so their peaks
+        // can land one FFT pass apart: count the neighbouring offsets too.
+
+        let mut best_votes: HashMap<usize, u32> = HashMap::new();
+        for (track, offset) in votes.keys() {
+            let count: u32 = (offset - 1..=offset + 1)
+                .filter_map(|neighbour| votes.get(&(*track, neighbour)))
+                .sum();
+            let entry = best_votes.entry(*track as usize).or_default();
+            *entry = (*entry).max(count);
+        }
+
+        for (candidate, count) in best_votes {
+            if candidate <= query || count < MIN_ALIGNED_LANDMARKS {
+                continue;
+            }
+
+            let smallest = query_landmarks.len().min(landmarks[candidate].len()).max(1);
+            let similarity = (count as f32 / smallest as f32).min(1.0);
+
+            if similarity >= min_similarity {
+                edges.push((query, candidate, similarity));
+            }
+        }
+    }
+
+    // Strongest links first, so a group's score is its weakest needed link
+
+    edges.sort_by(|a, b| b.2.total_cmp(&a.2));
+    for (first, second, similarity) in edges {
+        let (first_root, second_root) = (
+            find_root(&mut parents, first),
+            find_root(&mut parents, second),
+        );
+        if first_root == second_root {
+            continue;
+        }
+
+        let group_similarity = [
+            link_similarities.remove(&first_root),
+            link_similarities.remove(&second_root),
+            Some(similarity),
+        ]
+        .into_iter()
+        .flatten()
+        .fold(f32::INFINITY, f32::min);
+
+        parents[second_root] = first_root;
+        link_similarities.insert(first_root, group_similarity);
+    }
+
+    let mut groups: HashMap<usize, Vec<usize>> = HashMap::new();
+    for member in 0..signatures.len() {
+        let root = find_root(&mut parents, member);
+        groups.entry(root).or_default().push(member);
+    }
+
+    let mut duplicate_groups: Vec<DuplicateGroup> = groups
+        .into_iter()
+        .filter(|(_, members)| members.len() > 1)
+        .map(|(root, members)| DuplicateGroup {
+            members,
+            similarity: link_similarities[&root],
+        })
+        .collect();
+    duplicate_groups.sort_by_key(|group| group.members[0]);
+
+    duplicate_groups
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fingerprinting::signature_format::{FrequencyBand, FrequencyPeak};
+
+    /// Peaks of a random recording, in one band, a few FFT passes apart.
+    fn recording(rng: &mut fastrand::Rng, fft_passes: u32) -> Vec<FrequencyPeak> {
+        let mut peaks = vec![];
+        let mut fft_pass_number = 0;
+        while fft_pass_number < fft_passes {
+            peaks.push(FrequencyPeak {
+                fft_pass_number,
+                peak_magnitude: 7000,
+                corrected_peak_frequency_bin: rng.u16(..),
+            });
+            fft_pass_number += rng.u32(1..6);
+        }
+        peaks
+    }
+
+    /// Signature of the part of a recording between two FFT passes.
+    fn excerpt(peaks: &[FrequencyPeak], start: u32, end: u32) -> DecodedSignature {
+        let peaks = peaks
+            .iter()
+            .filter(|peak| (start..end).contains(&peak.fft_pass_number))
+            .map(|peak| FrequencyPeak {
+                fft_pass_number: peak.fft_pass_number - start,
+                ..*peak
+            })
+            .collect();
+
+        DecodedSignature {
+            sample_rate_hz: 16000,
+            number_samples: (end - start) * 128,
+            samples_used: (end - start) * 128,
+            frequency_band_to_sound_peaks: HashMap::from([(FrequencyBand::_520_1450, peaks)]),
+            quality: None,
+        }
+    }
+
+    #[test]
+    fn groups_signatures_linked_through_another_one() {
+        let mut rng = fastrand::Rng::with_seed(0x5EED);
+        let peaks = recording(&mut rng, 4000);
+
+        // The first and last parts do not overlap, the middle one overlaps both
+        let signatures = [
+            excerpt(&peaks, 0, 2000),
+            excerpt(&peaks, 1000, 3000),
+            excerpt(&peaks, 2000, 4000),
+        ];
+        let ends = [excerpt(&peaks, 0, 2000), excerpt(&peaks, 2000, 4000)];
+        assert!(find_duplicate_groups(&ends, 0.3).is_empty());
+
+        let groups = find_duplicate_groups(&signatures, 0.3);
+        assert_eq!(groups.len(), 1);
+        assert_eq!(groups[0].members, vec![0, 1, 2]);
+        assert!(groups[0].similarity >= 0.3 && groups[0].similarity <= 0.7);
+    }
+
+    #[test]
+    fn links_signatures_reaching_the_minimum_similarity() {
+        let mut rng = fastrand::Rng::with_seed(0x5EED);
+        let peaks = recording(&mut rng, 3000);
+        let signatures = [excerpt(&peaks, 0, 2000), excerpt(&peaks, 1000, 3000)];
+
+        let similarity = find_duplicate_groups(&signatures, 0.0)[0].similarity;
+        assert!(similarity > 0.0 && similarity < 1.0);
+
+        let groups = find_duplicate_groups(&signatures, similarity);
+        assert_eq!(groups.len(), 1);
+        assert_eq!(groups[0].similarity, similarity);
+        assert!(find_duplicate_groups(&signatures, similarity + f32::EPSILON).is_empty());
+    }
+
+    #[test]
+    fn finds_no_groups_among_different_recordings() {
+        let mut rng = fastrand::Rng::with_seed(0x5EED);
+        let signatures: Vec<_> = (0..5)
+            .map(|_| excerpt(&recording(&mut rng, 2000), 0, 2000))
+            .collect();
+
+        assert!(find_duplicate_groups(&signatures, 0.05).is_empty());
+        assert!(find_duplicate_groups(&signatures[..1], 0.05).is_empty());
+        assert!(find_duplicate_groups(&[], 0.05).is_empty());
+    }
+}
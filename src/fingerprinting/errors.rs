@@ -0,0 +1,177 @@
+use std::error::Error;
+use std::fmt;
+use std::time::Duration;
+
+use crate::fingerprinting::audio_format::AudioFormat;
+use crate::fingerprinting::audio_stream::StreamSelector;
+use crate::fingerprinting::signature_format::FrequencyBand;
+
+/// Errors raised while computing or encoding a signature. They reach Python
+/// as a `SignatureError` instead of aborting the whole process.
+#[derive(Debug, Clone, PartialEq)]
+pub enum FingerprintError {
+    UnexpectedFftLength {
+        expected: usize,
+        actual: usize,
+    },
+    InvalidPeakVariation {
+        fft_pass_number: u32,
+        bin_position: usize,
+        variation: f32,
+    },
+    UnsupportedSampleRate(u32),
+    UnorderedPeaks {
+        frequency_band: FrequencyBand,
+        previous_fft_pass_number: u32,
+        fft_pass_number: u32,
+    },
+    InputTooShort {
+        samples: usize,
+        minimum_samples: usize,
+    },
+    MalformedSignature(&'static str),
+    CorruptDatabase(&'static str),
+    UnsupportedDatabaseVersion(u32),
+    AudioStreamNotFound(StreamSelector),
+    InvalidPcm(String),
+    UnsupportedFormat(Option<AudioFormat>),
+    UnknownDecoder(String),
+    FFMpegNotFound,
+    FFProbeNotFound,
+    /// In-memory MP4 input with its metadata at the end, which FFMpeg cannot
+    /// read from a pipe.
+    UnseekableInput,
+    /// A codec which only FFMpeg decodes, such as Opus, while it could not
+    /// be run.
+    UnsupportedCodec(&'static str),
+    FFMpegTimedOut {
+        timeout: Duration,
+    },
+    /// FFMpeg produced no output for the inactivity timeout.
+    FFMpegStalled {
+        timeout: Duration,
+    },
+    FFMpegOutputTooLarge {
+        limit_bytes: u64,
+    },
+    FFMpegCancelled,
+    FFMpegFailed {
+        command_line: String,
+        exit_code: Option<i32>,
+        stderr: String,
+    },
+}
+
+impl fmt::Display for FingerprintError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FingerprintError::UnexpectedFftLength { expected, actual } => write!(
+                f,
+                "FFT produced {} bins, expected {}",
+                actual, expected
+            ),
+            FingerprintError::InvalidPeakVariation {
+                fft_pass_number,
+                bin_position,
+                variation,
+            } => write!(
+                f,
+                "Invalid peak variation {} at FFT pass {}, bin {}",
+                variation, fft_pass_number, bin_position
+            ),
+            FingerprintError::UnsupportedSampleRate(sample_rate) => write!(
+                f,
+                "Invalid sample rate {} Hz passed when encoding Shazam packet",
+                sample_rate
+            ),
+            FingerprintError::UnorderedPeaks {
+                frequency_band,
+                previous_fft_pass_number,
+                fft_pass_number,
+            } => write!(
+                f,
+                "Peaks of band {:?} are not ordered: FFT pass {} follows {}",
+                frequency_band, fft_pass_number, previous_fft_pass_number
+            ),
+            FingerprintError::InputTooShort {
+                samples,
+                minimum_samples,
+            } => write!(
+                f,
+                "Audio is too short to be fingerprinted: got {} samples ({:.2} s) at 16 KHz, need at least {} ({:.2} s)",
+                samples,
+                *samples as f32 / 16000.0,
+                minimum_samples,
+                *minimum_samples as f32 / 16000.0
+            ),
+            FingerprintError::MalformedSignature(reason) => {
+                write!(f, "Malformed signature: {}", reason)
+            }
+            FingerprintError::CorruptDatabase(reason) => {
+                write!(f, "Corrupt fingerprint database: {}", reason)
+            }
+            FingerprintError::UnsupportedDatabaseVersion(version) => write!(
+                f,
+                "Unsupported fingerprint database format version {}",
+                version
+            ),
+            FingerprintError::AudioStreamNotFound(StreamSelector::Index(index)) => {
+                write!(f, "There is no audio stream #{}", index)
+            }
+            FingerprintError::AudioStreamNotFound(StreamSelector::Language(language)) => {
+                write!(f, "There is no audio stream in language {:?}", language)
+            }
+            FingerprintError::InvalidPcm(reason) => write!(f, "Invalid PCM audio: {}", reason),
+            FingerprintError::UnsupportedFormat(Some(format)) => {
+                write!(f, "No decoder supports the {:?} format", format)
+            }
+            FingerprintError::UnsupportedFormat(None) => {
+                write!(f, "No decoder supports inputs of an unknown format")
+            }
+            FingerprintError::UnknownDecoder(name) => write!(f, "Unknown decoder {:?}", name),
+            FingerprintError::FFMpegNotFound => write!(f, "FFmpeg not found"),
+            FingerprintError::FFProbeNotFound => write!(f, "FFprobe not found"),
+            FingerprintError::UnseekableInput => write!(
+                f,
+                "FFMpeg cannot read MP4 input with its metadata at the end from memory, \
+                 pass it by path or allow temporary files"
+            ),
+            FingerprintError::UnsupportedCodec(codec) => write!(
+                f,
+                "{} audio is not supported without FFmpeg, which could not be run",
+                codec
+            ),
+            FingerprintError::FFMpegTimedOut { timeout } => write!(
+                f,
+                "FFMpeg did not finish converting the audio within {:.1} s",
+                timeout.as_secs_f64()
+            ),
+            FingerprintError::FFMpegStalled { timeout } => write!(
+                f,
+                "FFMpeg produced no output for {:.1} s",
+                timeout.as_secs_f64()
+            ),
+            FingerprintError::FFMpegOutputTooLarge { limit_bytes } => write!(
+                f,
+                "FFMpeg output exceeded the limit of {} bytes",
+                limit_bytes
+            ),
+            FingerprintError::FFMpegCancelled => write!(f, "FFMpeg conversion was cancelled"),
+            FingerprintError::FFMpegFailed {
+                exit_code, stderr, ..
+            } => {
+                match exit_code {
+                    Some(exit_code) => write!(f, "FFMpeg exited with code {}", exit_code)?,
+                    None => write!(f, "FFMpeg was terminated by a signal")?,
+                }
+                // The last line of the errors usually explains the failure
+                match stderr.trim().lines().last() {
+                    Some(reason) => write!(f, ": {}", reason),
+                    None => Ok(()),
+                }
+            }
+        }
+    }
+}
+
+impl Error for FingerprintError {}
@@ -1,143 +1,846 @@
 use std::error::Error;
-use std::fs::File;
-use std::io::{BufReader, Write};
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
 
 #[cfg(windows)]
 use std::os::windows::process::CommandExt;
 
-use rodio::Decoder;
+use crate::fingerprinting::audio_format::{sniff_format, AudioFormat};
+use crate::fingerprinting::audio_stream::StreamSelector;
+use crate::fingerprinting::decoder::{DecodeOptions, DecodedAudio};
+use crate::fingerprinting::errors::FingerprintError;
+use crate::fingerprinting::shared_bytes::SharedBytes;
+#[cfg(feature = "symphonia")]
+use crate::fingerprinting::symphonia_decoder::decode_with_symphonia;
+#[cfg(feature = "symphonia")]
+use log::debug;
+#[cfg(feature = "symphonia")]
 use std::io::Cursor;
-use std::process::Command;
-use tempfile::Builder;
+use std::process::{Child, Command, ExitStatus, Stdio};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError};
+use std::sync::{Arc, OnceLock};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
 
-/// This function used to decode a file with FFMpeg, if it is installed on
-/// the system, in the case where Rodio can't decode the concerned format
-/// (for example with .WMA, .M4A, etc.).
+/// Environment variable overriding the path of FFMpeg.
+pub const FFMPEG_PATH_VARIABLE: &str = "SHAZAMIO_FFMPEG";
 
-pub fn decode_with_ffmpeg(file_path: &str) -> Option<Decoder<BufReader<File>>> {
-    // Find the path for FFMpeg, in the case where it is installed
+/// Environment variable overriding the path of FFProbe.
+pub const FFPROBE_PATH_VARIABLE: &str = "SHAZAMIO_FFPROBE";
 
-    let mut possible_ffmpeg_paths: Vec<&str> = vec!["ffmpeg", "ffmpeg.exe"];
+/// FFMpeg found on the system, searched for once per process.
+static DISCOVERED_FFMPEG_PATH: OnceLock<Option<String>> = OnceLock::new();
 
-    let mut current_dir_ffmpeg_path =
-        std::env::current_exe().expect("failed current_dir_ffmpeg_path");
-    current_dir_ffmpeg_path.pop();
-    current_dir_ffmpeg_path.push("ffmpeg.exe");
-    possible_ffmpeg_paths.push(current_dir_ffmpeg_path.to_str().unwrap());
-    let mut actual_ffmpeg_path: Option<&str> = None;
+/// FFProbe found on the system, searched for once per process.
+static DISCOVERED_FFPROBE_PATH: OnceLock<Option<String>> = OnceLock::new();
 
-    for possible_path in possible_ffmpeg_paths {
-        // Use .output() to execute the subprocess testing for FFMpeg
-        // presence and correct execution, so that it does not pollute
-        // the standard or error output in any way
+/// Tells whether FFMpeg can be executed from the given path.
+pub fn is_working_ffmpeg(ffmpeg_path: &str) -> bool {
+    // Use .output() to execute the subprocess testing for FFMpeg
+    // presence and correct execution, so that it does not pollute
+    // the standard or error output in any way
 
-        let mut command = Command::new(possible_path);
-        #[cfg(windows)]
-        let command = command.creation_flags(0x08000000);
-        let command = command.arg("-version");
+    let mut command = Command::new(ffmpeg_path);
+    #[cfg(windows)]
+    let command = command.creation_flags(0x08000000);
+    let command = command.arg("-version");
+
+    command
+        .output()
+        .is_ok_and(|process| process.status.success())
+}
+
+/// Finds the path for a program of FFMpeg, such as `ffmpeg` or `ffprobe`,
+/// in the case where it is installed.
+fn discover_program(program: &str) -> Option<String> {
+    let executable = format!("{}.exe", program);
+    let mut possible_paths: Vec<String> = vec![program.into(), executable.clone()];
+
+    if let Ok(mut current_dir_path) = std::env::current_exe() {
+        current_dir_path.pop();
+        current_dir_path.push(&executable);
+        if let Some(path) = current_dir_path.to_str() {
+            possible_paths.push(path.to_string());
+        }
+    }
+
+    possible_paths
+        .into_iter()
+        .find(|possible_path| is_working_ffmpeg(possible_path))
+}
+
+/// Returns the path of a working FFProbe in the directory of the given
+/// FFMpeg, when it is given with one.
+fn ffprobe_next_to(ffmpeg_path: &str) -> Option<String> {
+    let directory = Path::new(ffmpeg_path).parent()?;
+    if directory.as_os_str().is_empty() {
+        return None;
+    }
+
+    ["ffprobe", "ffprobe.exe"]
+        .into_iter()
+        .map(|file_name| directory.join(file_name))
+        .filter(|path| path.is_file())
+        .filter_map(|path| path.to_str().map(String::from))
+        .find(|path| is_working_ffmpeg(path))
+}
+
+/// Time FFMpeg is given by default to run, from its start to its exit.
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(120);
+
+/// Default limit of FFMpeg output, a bit more than 4 hours of 16 KHz mono
+/// s16le samples.
+pub const DEFAULT_MAX_OUTPUT_BYTES: u64 = 512 * 1024 * 1024;
+
+/// Largest part of the error output of FFMpeg which is kept.
+const MAX_STDERR_BYTES: usize = 64 * 1024;
+
+/// How often a running FFMpeg is checked against the limits.
+const POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// Parts of the output FFMpeg can produce ahead of their processing.
+const OUTPUT_CHUNKS_IN_FLIGHT: usize = 16;
+
+#[derive(Debug, Clone)]
+pub struct FFMpegSettings {
+    /// Path of FFMpeg, overriding the environment variable and the search.
+    pub path: Option<String>,
+    /// Path of FFProbe, overriding the environment variable and the search.
+    pub ffprobe_path: Option<String>,
+    /// FFMpeg is killed when it runs for longer than this, counting from
+    /// its start to its exit.
+    pub timeout: Option<Duration>,
+    /// FFMpeg is killed when it is waited for this long without producing
+    /// any output. The time the output spends being processed does not
+    /// count.
+    pub inactivity_timeout: Option<Duration>,
+    /// FFMpeg is killed when it outputs more than this.
+    pub max_output_bytes: Option<u64>,
+    /// Set when the task using FFMpeg is cancelled, to kill it.
+    pub cancelled: Option<Arc<AtomicBool>>,
+    /// Allows in-memory input FFMpeg cannot read from a pipe to be written
+    /// to a temporary file, as a last resort.
+    pub temporary_files: bool,
+}
+
+impl Default for FFMpegSettings {
+    fn default() -> Self {
+        FFMpegSettings {
+            path: None,
+            ffprobe_path: None,
+            timeout: Some(DEFAULT_TIMEOUT),
+            inactivity_timeout: None,
+            max_output_bytes: Some(DEFAULT_MAX_OUTPUT_BYTES),
+            cancelled: None,
+            temporary_files: false,
+        }
+    }
+}
+
+impl FFMpegSettings {
+    /// Returns a copy of these settings for a task cancelled through the
+    /// given flag.
+    pub fn cancelled_by(&self, cancelled: Arc<AtomicBool>) -> Self {
+        FFMpegSettings {
+            cancelled: Some(cancelled),
+            ..self.clone()
+        }
+    }
+
+    fn is_cancelled(&self) -> bool {
+        self.cancelled
+            .as_ref()
+            .is_some_and(|cancelled| cancelled.load(Ordering::Relaxed))
+    }
 
-        if let Ok(process) = command.output() {
-            if process.status.success() {
-                actual_ffmpeg_path = Some(possible_path);
-                break;
+    /// Returns the configured path of FFMpeg, then the one given by the
+    /// `SHAZAMIO_FFMPEG` environment variable, then the one found on the
+    /// system. Explicit paths are not checked here.
+    pub fn resolve_path(&self) -> Option<String> {
+        if let Some(path) = &self.path {
+            return Some(path.clone());
+        }
+
+        if let Some(path) = std::env::var_os(FFMPEG_PATH_VARIABLE) {
+            if !path.is_empty() {
+                return Some(path.to_string_lossy().into_owned());
             }
         }
+
+        DISCOVERED_FFMPEG_PATH
+            .get_or_init(|| discover_program("ffmpeg"))
+            .clone()
     }
 
-    // If FFMpeg is available, use it to convert the input file
-    // from whichever format to a .WAV (because Rodio has its
-    // decoding support limited to .WAV, .FLAC, .OGG, .MP3, which
-    // makes that .MP4/.AAC, .OPUS or .WMA are not supported, and
-    // Rodio's minimp3 .MP3 decoder seems to crash on Windows anyway)
-    if let Some(ffmpeg_path) = actual_ffmpeg_path {
-        // Create a sink file for FFMpeg
+    /// Returns the configured path of FFProbe, then the one given by the
+    /// `SHAZAMIO_FFPROBE` environment variable, then the one next to an
+    /// FFMpeg given with its directory, then the one found on the system.
+    /// Explicit paths are not checked here.
+    pub fn resolve_ffprobe_path(&self) -> Option<String> {
+        if let Some(path) = &self.ffprobe_path {
+            return Some(path.clone());
+        }
+
+        if let Some(path) = std::env::var_os(FFPROBE_PATH_VARIABLE) {
+            if !path.is_empty() {
+                return Some(path.to_string_lossy().into_owned());
+            }
+        }
+
+        if let Some(path) = self.resolve_path().and_then(|path| ffprobe_next_to(&path)) {
+            return Some(path);
+        }
 
-        let sink_file = Builder::new().suffix(".wav").tempfile().unwrap();
+        DISCOVERED_FFPROBE_PATH
+            .get_or_init(|| discover_program("ffprobe"))
+            .clone()
+    }
+}
 
-        let sink_file_path = sink_file.into_temp_path();
-        // Try to convert the input video or audio file to a standard
-        // .WAV s16le PCM file using FFMpeg, and pass it to Rodio
-        // later in the case where it succeeded
+/// Kills FFMpeg if it is still running when it is no longer waited for.
+struct ChildGuard(Child);
 
+impl Drop for ChildGuard {
+    fn drop(&mut self) {
+        if let Ok(None) = self.0.try_wait() {
+            let _ = self.0.kill();
+            let _ = self.0.wait();
+        }
+    }
+}
+
+/// Copy of an input FFMpeg cannot read from a pipe, removed once dropped.
+struct TemporaryInput(PathBuf);
+
+impl TemporaryInput {
+    fn write(bytes: &SharedBytes, extension: &str) -> std::io::Result<Self> {
+        let path = std::env::temp_dir().join(format!(
+            "shazamio-core-{}-{:016x}.{}",
+            std::process::id(),
+            fastrand::u64(..),
+            extension
+        ));
+
+        // Never follow a file planted at the same path
+        let mut file = std::fs::OpenOptions::new().write(true).create_new(true).open(&path)?;
+        let input = TemporaryInput(path);
+        file.write_all(bytes.as_ref())?;
+        Ok(input)
+    }
+
+    fn path(&self) -> Result<&str, Box<dyn Error>> {
+        self.0.to_str().ok_or_else(|| "Temporary directory path is not UTF-8".into())
+    }
+}
+
+impl Drop for TemporaryInput {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.0);
+    }
+}
+
+/// Tells whether the metadata ("moov" box) of an MP4, M4A or QuickTime
+/// file comes after its media data, as most encoders write it. FFMpeg then
+/// needs to seek back to the media data, which it cannot do on a pipe.
+fn has_trailing_mp4_metadata(data: &[u8]) -> bool {
+    let mut position = 0usize;
+
+    while let Some(header) = data.get(position..position + 8) {
+        let box_type = &header[4..8];
+        if box_type == b"moov" {
+            return false;
+        }
+        if box_type == b"mdat" {
+            return true;
+        }
+
+        let size = match u32::from_be_bytes(header[0..4].try_into().unwrap()) {
+            // The last box extends to the end of the file
+            0 => return true,
+            1 => match data.get(position + 8..position + 16) {
+                Some(large_size) => u64::from_be_bytes(large_size.try_into().unwrap()),
+                None => return true,
+            },
+            size => size as u64,
+        };
+        if size < 8 {
+            return true;
+        }
+        position = match usize::try_from(size).ok().and_then(|size| position.checked_add(size)) {
+            Some(position) => position,
+            None => return true,
+        };
+    }
+
+    // Without any metadata, let FFMpeg report what it makes of the input
+    false
+}
+
+/// Output of a finished FFMpeg process.
+struct FFMpegOutput {
+    command_line: String,
+    status: ExitStatus,
+    stdout: Vec<u8>,
+    stderr: Vec<u8>,
+}
+
+impl FFMpegOutput {
+    /// Returns the output of a successful process, or describes the failure
+    /// of FFMpeg with its errors.
+    fn success(self) -> Result<Self, FingerprintError> {
+        if self.status.success() {
+            return Ok(self);
+        }
+
+        Err(FingerprintError::FFMpegFailed {
+            command_line: self.command_line,
+            exit_code: self.status.code(),
+            stderr: String::from_utf8_lossy(&self.stderr).into_owned(),
+        })
+    }
+}
+
+/// Formats a command line for error messages, quoting the arguments which
+/// need it.
+fn format_command_line(program: &str, args: &[String]) -> String {
+    std::iter::once(program)
+        .chain(args.iter().map(String::as_str))
+        .map(|arg| {
+            if arg.is_empty() || arg.contains(|c: char| c.is_whitespace() || c == '"' || c == '\'') {
+                format!("{:?}", arg)
+            } else {
+                arg.to_string()
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// A running FFMpeg process, whose output is read as it is produced.
+struct FFMpegProcess {
+    command_line: String,
+    process: ChildGuard,
+    started: Instant,
+    settings: FFMpegSettings,
+    output: Receiver<Result<Vec<u8>, Box<dyn Error + Send + Sync>>>,
+    stderr_reader: JoinHandle<Vec<u8>>,
+}
+
+impl FFMpegProcess {
+    /// Starts FFMpeg with the given arguments, optionally writing `input` to
+    /// its standard input. Nothing is written to disk.
+    fn spawn(
+        ffmpeg_path: &str,
+        args: &[String],
+        input: Option<SharedBytes>,
+        settings: &FFMpegSettings,
+    ) -> Result<Self, Box<dyn Error>> {
         let mut command = Command::new(ffmpeg_path);
 
+        // Set "CREATE_NO_WINDOW" on Windows, see
+        // https://stackoverflow.com/a/60958956/662399
+
         #[cfg(windows)]
         let command = command.creation_flags(0x08000000);
 
-        let command = command.args(["-y", "-i", file_path, sink_file_path.to_str().unwrap()]);
+        let command = command
+            .args(args)
+            .stdin(if input.is_some() { Stdio::piped() } else { Stdio::null() })
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped());
 
-        // Set "CREATE_NO_WINDOW" on Windows, see
-        // https://stackoverflow.com/a/60958956/662399
+        // A configured path may not exist, like a missing installation
+        let started = Instant::now();
+        let mut process = ChildGuard(command.spawn().map_err(|error| -> Box<dyn Error> {
+            match error.kind() {
+                std::io::ErrorKind::NotFound => Box::new(FingerprintError::FFMpegNotFound),
+                _ => Box::new(error),
+            }
+        })?);
+
+        // Feed the input from another thread, as FFMpeg may need its output
+        // to be read before it consumes all of its input
+
+        if let (Some(input), Some(mut stdin)) = (input, process.0.stdin.take()) {
+            thread::spawn(move || {
+                // FFMpeg closes its input early when it has read enough of it
+                let _ = stdin.write_all(input.as_ref());
+            });
+        }
+
+        // Read the outputs from other threads too, so that the limits can be
+        // checked while FFMpeg runs
+
+        let mut stderr = process.0.stderr.take().ok_or("FFMpeg errors are not piped")?;
+        let stderr_reader = thread::spawn(move || {
+            let mut errors = vec![];
+            let mut chunk = [0u8; 4096];
+
+            // Only keep the end, which explains why FFMpeg failed
+            while let Ok(read) = stderr.read(&mut chunk) {
+                if read == 0 {
+                    break;
+                }
+                errors.extend_from_slice(&chunk[..read]);
+                if errors.len() > 2 * MAX_STDERR_BYTES {
+                    errors.drain(..errors.len() - MAX_STDERR_BYTES);
+                }
+            }
+            errors
+        });
+
+        // The channel is bounded, so that FFMpeg waits while the output it
+        // already produced is being processed
+
+        let mut stdout = process.0.stdout.take().ok_or("FFMpeg output is not piped")?;
+        let max_output_bytes = settings.max_output_bytes;
+        let (sender, output) = mpsc::sync_channel(OUTPUT_CHUNKS_IN_FLIGHT);
+        thread::spawn(move || {
+            let mut output_bytes = 0u64;
+            let mut chunk = [0u8; 65536];
+
+            loop {
+                let read = match stdout.read(&mut chunk) {
+                    Ok(0) => return,
+                    Ok(read) => read,
+                    Err(error) => {
+                        let _ = sender.send(Err(error.into()));
+                        return;
+                    }
+                };
+                output_bytes += read as u64;
+                if let Some(limit_bytes) = max_output_bytes {
+                    if output_bytes > limit_bytes {
+                        let _ = sender.send(Err(FingerprintError::FFMpegOutputTooLarge { limit_bytes }.into()));
+                        return;
+                    }
+                }
+                // The output is no longer wanted when the receiver is gone
+                if sender.send(Ok(chunk[..read].to_vec())).is_err() {
+                    return;
+                }
+            }
+        });
 
-        if let Ok(process) = command.output() {
-            if process.status.success() {
-                let res = Decoder::new(BufReader::new(
-                    File::open(sink_file_path.to_str().unwrap()).unwrap(),
-                ))
-                .expect("failed to decode with ffmpeg");
-                return Some(res);
+        Ok(FFMpegProcess {
+            command_line: format_command_line(ffmpeg_path, args),
+            process,
+            started,
+            settings: settings.clone(),
+            output,
+            stderr_reader,
+        })
+    }
+
+    /// Fails when FFMpeg has run for longer than its timeout, or when the
+    /// task is cancelled.
+    fn check_limits(&self) -> Result<(), Box<dyn Error>> {
+        if self.settings.is_cancelled() {
+            return Err(FingerprintError::FFMpegCancelled.into());
+        }
+        if let Some(timeout) = self.settings.timeout {
+            if self.started.elapsed() > timeout {
+                return Err(FingerprintError::FFMpegTimedOut { timeout }.into());
             }
-        } else {
-            return None;
         }
+        Ok(())
     }
-    None
-}
 
-pub fn decode_with_ffmpeg_from_bytes(
-    bytes: &[u8],
-) -> Result<Decoder<Cursor<Vec<u8>>>, Box<dyn Error>> {
-    // Create a temporary file for the input
-    let mut temp_file = Builder::new().suffix(".tmp").tempfile()?;
-    temp_file.write_all(bytes)?;
-    let file_path = temp_file.path().to_str().unwrap().to_string();
-
-    // Find the FFmpeg path
-    let mut possible_ffmpeg_paths = vec!["ffmpeg", "ffmpeg.exe"];
-    let mut current_dir_ffmpeg_path =
-        std::env::current_exe().expect("failed current_dir_ffmpeg_path");
-    current_dir_ffmpeg_path.pop();
-    current_dir_ffmpeg_path.push("ffmpeg.exe");
-    possible_ffmpeg_paths.push(current_dir_ffmpeg_path.to_str().unwrap());
-
-    let mut actual_ffmpeg_path = None;
-    for possible_path in &possible_ffmpeg_paths {
-        let mut command = Command::new(possible_path);
-        #[cfg(windows)]
-        let command = command.creation_flags(0x08000000);
-        let command = command.arg("-version");
+    /// Waits for the next part of the output, `None` once FFMpeg closed it.
+    /// FFMpeg is killed when it exceeds the timeouts of its settings, when
+    /// its output exceeds their limit, or when the task is cancelled.
+    fn read_chunk(&mut self) -> Result<Option<Vec<u8>>, Box<dyn Error>> {
+        let waiting_since = Instant::now();
 
-        if let Ok(process) = command.output() {
-            if process.status.success() {
-                actual_ffmpeg_path = Some(*possible_path);
-                break;
+        loop {
+            self.check_limits()?;
+            if let Some(timeout) = self.settings.inactivity_timeout {
+                if waiting_since.elapsed() > timeout {
+                    return Err(FingerprintError::FFMpegStalled { timeout }.into());
+                }
+            }
+
+            match self.output.recv_timeout(POLL_INTERVAL) {
+                Ok(Ok(chunk)) => return Ok(Some(chunk)),
+                Ok(Err(error)) => return Err(error),
+                Err(RecvTimeoutError::Timeout) => {}
+                Err(RecvTimeoutError::Disconnected) => return Ok(None),
             }
         }
     }
 
-    if let Some(ffmpeg_path) = actual_ffmpeg_path {
-        // Create a temporary file for the output
-        let sink_file = Builder::new().suffix(".wav").tempfile()?;
-        let sink_file_path = sink_file.path().to_str().unwrap().to_string();
+    /// Waits for FFMpeg to exit, once its output was read. It is killed
+    /// when it exceeds its timeout meanwhile, or when the task is cancelled.
+    fn wait(mut self, stdout: Vec<u8>) -> Result<FFMpegOutput, Box<dyn Error>> {
+        let status = loop {
+            if let Some(status) = self.process.0.try_wait()? {
+                break status;
+            }
+            self.check_limits()?;
+            thread::sleep(POLL_INTERVAL);
+        };
+        let stderr = self.stderr_reader.join().unwrap_or_default();
 
-        // Convert to WAV format
-        let mut command = Command::new(ffmpeg_path);
-        #[cfg(windows)]
-        let command = command.creation_flags(0x08000000);
-        let command = command.args(["-y", "-i", &file_path, &sink_file_path]);
+        Ok(FFMpegOutput {
+            command_line: self.command_line,
+            status,
+            stdout,
+            stderr,
+        })
+    }
+}
+
+/// Runs FFMpeg with the given arguments, optionally writing `input` to its
+/// standard input, and collects its outputs. Nothing is written to disk.
+/// FFMpeg is killed when it exceeds the time or output limits of
+/// `settings`, or when the task is cancelled.
+fn run_ffmpeg(
+    ffmpeg_path: &str,
+    args: &[String],
+    input: Option<SharedBytes>,
+    settings: &FFMpegSettings,
+) -> Result<FFMpegOutput, Box<dyn Error>> {
+    let mut process = FFMpegProcess::spawn(ffmpeg_path, args, input, settings)?;
+
+    let mut stdout = vec![];
+    while let Some(chunk) = process.read_chunk()? {
+        stdout.extend_from_slice(&chunk);
+    }
+
+    process.wait(stdout)
+}
 
-        if let Ok(process) = command.output() {
-            if process.status.success() {
-                // Read the converted file into a Vec<u8>
-                let converted_bytes = std::fs::read(sink_file_path)?;
+/// 16 KHz mono samples read from FFMpeg as it converts its input. The
+/// failure of FFMpeg is reported once its output ends.
+struct FFMpegSamples {
+    process: Option<FFMpegProcess>,
+    /// Byte of a sample split between two parts of the output.
+    split_byte: Option<u8>,
+    /// Removed once FFMpeg no longer reads it.
+    _temporary_input: Option<TemporaryInput>,
+}
 
-                // Create a Cursor around the converted bytes and create a Decoder
-                let cursor = Cursor::new(converted_bytes);
-                let decoder = Decoder::new(cursor)?;
+impl FFMpegSamples {
+    fn next_chunk(&mut self) -> Result<Option<Vec<i16>>, Box<dyn Error>> {
+        let Some(process) = self.process.as_mut() else {
+            return Ok(None);
+        };
 
-                return Ok(decoder);
+        let Some(bytes) = process.read_chunk()? else {
+            if let Some(process) = self.process.take() {
+                process.wait(vec![])?.success()?;
             }
+            return Ok(None);
+        };
+
+        let mut bytes = bytes.as_slice();
+        let mut samples = Vec::with_capacity(bytes.len() / 2 + 1);
+
+        if let (Some(low), Some((high, rest))) = (self.split_byte, bytes.split_first()) {
+            samples.push(i16::from_le_bytes([low, *high]));
+            self.split_byte = None;
+            bytes = rest;
+        }
+
+        let mut pairs = bytes.chunks_exact(2);
+        samples.extend((&mut pairs).map(|sample| i16::from_le_bytes([sample[0], sample[1]])));
+        if let [byte] = pairs.remainder() {
+            self.split_byte = Some(*byte);
         }
+
+        Ok(Some(samples))
+    }
+}
+
+impl Iterator for FFMpegSamples {
+    type Item = Result<Vec<i16>, Box<dyn Error>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let chunk = self.next_chunk();
+        if chunk.is_err() {
+            // FFMpeg is killed when the error ends the audio
+            self.process = None;
+        }
+        chunk.transpose()
+    }
+}
+
+/// Parses the duration FFMpeg prints about its input, such as
+/// "Duration: 00:03:25.47, start: 0.000000, bitrate: 128 kb/s".
+fn parse_duration(ffmpeg_errors: &str) -> Option<Duration> {
+    let line = ffmpeg_errors
+        .lines()
+        .find_map(|line| line.trim_start().strip_prefix("Duration: "))?;
+    let timestamp = line.split(',').next()?.trim();
+
+    let mut seconds = 0.0;
+    for part in timestamp.split(':') {
+        seconds = seconds * 60.0 + part.parse::<f64>().ok()?;
+    }
+
+    Some(Duration::from_secs_f64(seconds))
+}
+
+/// Probes the duration of the input with FFMpeg, when its container tells.
+fn probe_duration(
+    ffmpeg_path: &str,
+    input_path: &str,
+    input: Option<SharedBytes>,
+    settings: &FFMpegSettings,
+) -> Result<Option<Duration>, Box<dyn Error>> {
+    // Without an output file FFMpeg only prints the input information,
+    // and then exits with an error
+
+    let args: Vec<String> = vec!["-hide_banner".into(), "-nostdin".into(), "-i".into(), input_path.into()];
+    let output = run_ffmpeg(ffmpeg_path, &args, input, settings)?;
+
+    Ok(parse_duration(&String::from_utf8_lossy(&output.stderr)))
+}
+
+/// Converts the input with FFMpeg to 16 KHz mono s16le PCM samples, read as
+/// they are converted. When a `segment` duration is requested and the
+/// input is longer than that, only the segment in the middle of the input is
+/// converted. The audio stream picked by FFMpeg is used unless a `stream` is
+/// selected.
+fn convert_with_ffmpeg(
+    ffmpeg_path: &str,
+    input_path: &str,
+    input: Option<SharedBytes>,
+    temporary_input: Option<TemporaryInput>,
+    options: &DecodeOptions,
+) -> Result<DecodedAudio, Box<dyn Error>> {
+    let settings = options.ffmpeg;
+    let mut args: Vec<String> = vec!["-hide_banner".into(), "-nostdin".into()];
+
+    // Streams can only be mapped by language through their position
+    let stream_position = match options.stream {
+        Some(StreamSelector::Index(index)) => Some(*index),
+        Some(stream) => {
+            let languages = list_audio_streams(input_path, input.clone(), settings)?;
+            Some(
+                stream
+                    .position(&languages)
+                    .ok_or_else(|| FingerprintError::AudioStreamNotFound(stream.clone()))?,
+            )
+        }
+        None => None,
+    };
+
+    let mut duration = options.duration;
+
+    if let Some(segment) = options.segment {
+        if duration.is_none() {
+            duration = probe_duration(ffmpeg_path, input_path, input.clone(), settings)?;
+        }
+
+        // Placed before the input, -ss seeks in the input rather than
+        // decoding and dropping everything before the segment
+
+        if let Some(total_duration) = duration.filter(|duration| *duration > segment) {
+            let start = (total_duration - segment) / 2;
+            args.extend([
+                "-ss".into(),
+                format!("{:.6}", start.as_secs_f64()),
+                "-t".into(),
+                format!("{:.6}", segment.as_secs_f64()),
+            ]);
+            duration = Some(segment);
+        }
+    }
+
+    // Convert the input video or audio file straight to the raw format
+    // the signature generator expects, using FFMpeg's resampler. Only
+    // errors are printed, to explain failures
+
+    args.extend(["-v".into(), "error".into(), "-i".into(), input_path.into()]);
+    if let Some(stream_position) = stream_position {
+        args.extend(["-map".into(), format!("0:a:{}", stream_position)]);
+    }
+    args.extend(
+        ["-vn", "-ac", "1", "-ar", "16000", "-f", "s16le", "-acodec", "pcm_s16le", "pipe:1"]
+            .map(String::from),
+    );
+
+    let mut samples = FFMpegSamples {
+        process: Some(FFMpegProcess::spawn(ffmpeg_path, &args, input, settings)?),
+        split_byte: None,
+        _temporary_input: temporary_input,
+    };
+
+    // Wait for the first samples, so that FFMpeg failing to read the input
+    // lets the next decoder be tried
+    let first_chunk = samples.next().transpose()?;
+
+    Ok(DecodedAudio {
+        duration,
+        ..DecodedAudio::new(16000, 1, first_chunk.map(Ok).into_iter().chain(samples))
+    })
+}
+
+/// This function used to decode a file with FFMpeg, if it is installed on
+/// the system, in the case where Rodio can't decode the concerned format
+/// (for example with .WMA, .M4A, etc.). Returns 16 KHz mono samples, of the
+/// middle segment of the file only if requested by `options`.
+pub fn decode_with_ffmpeg(file_path: &str, options: &DecodeOptions) -> Result<DecodedAudio, Box<dyn Error>> {
+    // If FFMpeg is available, use it to convert the input file
+    // from whichever format to raw PCM (because Rodio has its
+    // decoding support limited to .WAV, .FLAC, .OGG, .MP3, which
+    // makes that .MP4/.AAC, .OPUS or .WMA are not supported, and
+    // Rodio's minimp3 .MP3 decoder seems to crash on Windows anyway)
+
+    let ffmpeg_path = options.ffmpeg.resolve_path().ok_or(FingerprintError::FFMpegNotFound)?;
+    convert_with_ffmpeg(&ffmpeg_path, file_path, None, None, options)
+}
+
+pub fn decode_with_ffmpeg_from_bytes(
+    bytes: SharedBytes,
+    options: &DecodeOptions,
+) -> Result<DecodedAudio, Box<dyn Error>> {
+    // FFMpeg cannot read MP4 files with their metadata at the end from a
+    // pipe. Symphonia seeks in memory instead, and writing them to disk is
+    // only allowed by the settings
+
+    if sniff_format(bytes.as_ref()) == Some(AudioFormat::Mp4) && has_trailing_mp4_metadata(bytes.as_ref()) {
+        #[cfg(feature = "symphonia")]
+        match decode_with_symphonia(Box::new(Cursor::new(bytes.clone())), Some("mp4"), options) {
+            Ok(audio) => return Ok(audio),
+            Err(error) => debug!("Symphonia failed to decode MP4 input with trailing metadata: {}", error),
+        }
+
+        if !options.ffmpeg.temporary_files {
+            return Err(FingerprintError::UnseekableInput.into());
+        }
+
+        let ffmpeg_path = options.ffmpeg.resolve_path().ok_or(FingerprintError::FFMpegNotFound)?;
+        let temporary_input = TemporaryInput::write(&bytes, "mp4")?;
+        let input_path = temporary_input.path()?.to_string();
+        return convert_with_ffmpeg(&ffmpeg_path, &input_path, None, Some(temporary_input), options);
+    }
+
+    // The input is piped through the standard input
+    let ffmpeg_path = options.ffmpeg.resolve_path().ok_or(FingerprintError::FFMpegNotFound)?;
+    convert_with_ffmpeg(&ffmpeg_path, "pipe:0", Some(bytes), None, options)
+}
+
+/// Describes the container and streams of the input with FFProbe, in its
+/// JSON format. The input is read from `input` if given, from the file at
+/// `input_path` otherwise.
+pub fn run_ffprobe(
+    input_path: &str,
+    input: Option<SharedBytes>,
+    settings: &FFMpegSettings,
+) -> Result<serde_json::Value, Box<dyn Error>> {
+    let ffprobe_path = settings.resolve_ffprobe_path().ok_or(FingerprintError::FFProbeNotFound)?;
+
+    let args: Vec<String> = [
+        "-v",
+        "error",
+        "-print_format",
+        "json",
+        "-show_format",
+        "-show_streams",
+        input_path,
+    ]
+    .map(String::from)
+    .to_vec();
+
+    let output = run_ffmpeg(&ffprobe_path, &args, input, settings)
+        .map_err(|error| match error.downcast_ref::<FingerprintError>() {
+            Some(FingerprintError::FFMpegNotFound) => FingerprintError::FFProbeNotFound.into(),
+            _ => error,
+        })?
+        .success()?;
+
+    Ok(serde_json::from_slice(&output.stdout)?)
+}
+
+/// Lists the language of every audio stream of the input with FFProbe, in
+/// order, `None` for the streams which are not tagged.
+pub fn list_audio_streams(
+    input_path: &str,
+    input: Option<SharedBytes>,
+    settings: &FFMpegSettings,
+) -> Result<Vec<Option<String>>, Box<dyn Error>> {
+    let description = run_ffprobe(input_path, input, settings)?;
+
+    Ok(description["streams"]
+        .as_array()
+        .map(|streams| {
+            streams
+                .iter()
+                .filter(|stream| stream["codec_type"].as_str() == Some("audio"))
+                .map(|stream| stream["tags"]["language"].as_str().map(String::from))
+                .collect()
+        })
+        .unwrap_or_default())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn mp4_box(box_type: &[u8; 4], payload_size: usize) -> Vec<u8> {
+        let mut data = ((payload_size + 8) as u32).to_be_bytes().to_vec();
+        data.extend_from_slice(box_type);
+        data.resize(payload_size + 8, 0);
+        data
+    }
+
+    #[test]
+    fn finds_mp4_metadata_after_the_media_data() {
+        let ftyp = mp4_box(b"ftyp", 16);
+        let moov = mp4_box(b"moov", 100);
+        let mdat = mp4_box(b"mdat", 1000);
+
+        let faststart = [ftyp.clone(), moov.clone(), mdat.clone()].concat();
+        assert!(!has_trailing_mp4_metadata(&faststart));
+
+        let trailing = [ftyp.clone(), mdat, moov].concat();
+        assert!(has_trailing_mp4_metadata(&trailing));
+
+        // Box sizes which cannot be trusted are read from a file too
+        let mut malformed = ftyp;
+        malformed.extend_from_slice(&[0, 0, 0, 4, b'f', b'r', b'e', b'e']);
+        assert!(has_trailing_mp4_metadata(&malformed));
+        assert!(has_trailing_mp4_metadata(&[0, 0, 0, 1, b'w', b'i', b'd', b'e', 0xFF]));
+    }
+
+    #[test]
+    fn keeps_mp4_input_with_trailing_metadata_off_the_disk() {
+        let trailing = [mp4_box(b"ftyp", 16), mp4_box(b"mdat", 1000), mp4_box(b"moov", 100)].concat();
+        let settings = FFMpegSettings {
+            path: Some("ffmpeg-which-must-not-run".to_string()),
+            ..FFMpegSettings::default()
+        };
+        let options = DecodeOptions {
+            segment: None,
+            stream: None,
+            ffmpeg: &settings,
+            duration: None,
+        };
+
+        // Neither Symphonia nor FFMpeg can decode it without a temporary file
+        let error = decode_with_ffmpeg_from_bytes(SharedBytes::from(trailing), &options).unwrap_err();
+        assert!(matches!(
+            error.downcast_ref::<FingerprintError>(),
+            Some(FingerprintError::UnseekableInput)
+        ));
+    }
+
+    #[test]
+    fn reports_a_missing_ffprobe_apart_from_ffmpeg() {
+        let settings = FFMpegSettings {
+            path: Some("ffmpeg-which-must-not-run".to_string()),
+            ffprobe_path: Some("ffprobe-which-does-not-exist".to_string()),
+            ..FFMpegSettings::default()
+        };
+        assert_eq!(settings.resolve_ffprobe_path().as_deref(), Some("ffprobe-which-does-not-exist"));
+
+        let error = run_ffprobe("pipe:0", Some(SharedBytes::from(vec![0; 16])), &settings).unwrap_err();
+        assert!(matches!(
+            error.downcast_ref::<FingerprintError>(),
+            Some(FingerprintError::FFProbeNotFound)
+        ));
     }
-    Err("FFmpeg not found or failed to convert audio".into())
 }
@@ -0,0 +1,310 @@
+use crate::fingerprinting::signature_format::{DecodedSignature, FrequencyPeak};
+use std::collections::HashMap;
+
+/// Number of later peaks each anchor peak is paired with.
+const FAN_OUT: usize = 5;
+
+/// Largest time distance between paired peaks, in FFT passes (about 0.5 s).
+const MAX_DELTA_FFT_PASSES: u32 = 63;
+
+/// Duration of one FFT pass: 128 samples at 16 KHz.
+pub const SECONDS_PER_FFT_PASS: f32 = 128.0 / 16000.0;
+
+/// A hash formed from a pair of peaks of the same frequency band, along with
+/// the time of the first (anchor) peak.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Landmark {
+    pub hash: u32,
+    pub anchor_fft_pass_number: u32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Posting {
+    pub track: u32,
+    pub fft_pass_number: u32,
+}
+
+/// Vote counts by (track, time offset in FFT passes).
+pub type Votes = HashMap<(u32, i64), u32>;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct LandmarkMatch {
+    pub track_id: String,
+    /// Position of the query start within the matched track, in FFT passes.
+    pub offset_fft_passes: i64,
+    /// Number of landmark hashes agreeing on this track and offset.
+    pub votes: u32,
+}
+
+impl LandmarkMatch {
+    pub fn offset_seconds(&self) -> f32 {
+        self.offset_fft_passes as f32 * SECONDS_PER_FFT_PASS
+    }
+}
+
+/// Packs (band, anchor bin, target bin, Δt) into 32 bits: 2 bits of band,
+/// 10 bits per FFT bin (the corrected bins are stored multiplied by 64) and
+/// 8 bits of time delta.
+fn landmark_hash(band: u32, anchor: &FrequencyPeak, target: &FrequencyPeak) -> u32 {
+    let anchor_bin = (anchor.corrected_peak_frequency_bin >> 6) as u32 & 0x3ff;
+    let target_bin = (target.corrected_peak_frequency_bin >> 6) as u32 & 0x3ff;
+    let delta = (target.fft_pass_number - anchor.fft_pass_number) & 0xff;
+
+    (band << 28) | (anchor_bin << 18) | (target_bin << 8) | delta
+}
+
+pub fn landmarks_from_signature(signature: &DecodedSignature) -> Vec<Landmark> {
+    let mut landmarks = vec![];
+
+    for (frequency_band, frequency_peaks) in &signature.frequency_band_to_sound_peaks {
+        for (index, anchor) in frequency_peaks.iter().enumerate() {
+            let targets = frequency_peaks[index + 1..]
+                .iter()
+                .filter(|target| target.fft_pass_number > anchor.fft_pass_number)
+                .take_while(|target| {
+                    target.fft_pass_number - anchor.fft_pass_number <= MAX_DELTA_FFT_PASSES
+                })
+                .take(FAN_OUT);
+
+            for target in targets {
+                landmarks.push(Landmark {
+                    hash: landmark_hash(*frequency_band as u32, anchor, target),
+                    anchor_fft_pass_number: anchor.fft_pass_number,
+                });
+            }
+        }
+    }
+
+    landmarks
+}
+
+/// Counts one vote per posting, for the offset between the posting and the
+/// query landmark.
+pub fn cast_votes(landmark: &Landmark, postings: impl Iterator<Item = Posting>, votes: &mut Votes) {
+    for posting in postings {
+        let offset = posting.fft_pass_number as i64 - landmark.anchor_fft_pass_number as i64;
+        *votes.entry((posting.track, offset)).or_default() += 1;
+    }
+}
+
+pub fn best_match(votes: Votes, track_ids: &[String]) -> Option<LandmarkMatch> {
+    votes
+        .into_iter()
+        .max_by(|(key_a, votes_a), (key_b, votes_b)| {
+            // Prefer the earliest track and offset on ties, to stay deterministic
+            votes_a.cmp(votes_b).then_with(|| key_b.cmp(key_a))
+        })
+        .map(|((track, offset), votes)| LandmarkMatch {
+            track_id: track_ids[track as usize].clone(),
+            offset_fft_passes: offset,
+            votes,
+        })
+}
+
+/// In-memory inverted index from landmark hashes to the tracks and times
+/// they occur at, used to recognize audio against a local catalogue.
+#[derive(Default)]
+pub struct LandmarkIndex {
+    track_ids: Vec<String>,
+    tracks_by_id: HashMap<String, u32>,
+    postings: HashMap<u32, Vec<Posting>>,
+}
+
+impl LandmarkIndex {
+    pub fn new() -> Self {
+        LandmarkIndex::default()
+    }
+
+    pub fn track_count(&self) -> usize {
+        self.track_ids.len()
+    }
+
+    pub fn hash_count(&self) -> usize {
+        self.postings.len()
+    }
+
+    pub fn track_ids(&self) -> &[String] {
+        &self.track_ids
+    }
+
+    pub fn postings(&self) -> &HashMap<u32, Vec<Posting>> {
+        &self.postings
+    }
+
+    /// Returns the internal number of a track, registering it if needed.
+    pub fn track_number(&mut self, track_id: &str) -> u32 {
+        if let Some(track) = self.tracks_by_id.get(track_id) {
+            return *track;
+        }
+
+        let track = self.track_ids.len() as u32;
+        self.track_ids.push(track_id.to_string());
+        self.tracks_by_id.insert(track_id.to_string(), track);
+        track
+    }
+
+    pub fn add_landmarks(&mut self, track_id: &str, landmarks: &[Landmark]) {
+        let track = self.track_number(track_id);
+
+        for landmark in landmarks {
+            self.postings.entry(landmark.hash).or_default().push(Posting {
+                track,
+                fft_pass_number: landmark.anchor_fft_pass_number,
+            });
+        }
+    }
+
+    /// Indexes the peaks of a signature under `track_id`. Signatures added
+    /// under the same ID are overlaid, each starting at offset 0 of the
+    /// track, so that matches report offsets within any of them.
+    pub fn add_signature(&mut self, track_id: &str, signature: &DecodedSignature) {
+        self.add_landmarks(track_id, &landmarks_from_signature(signature));
+    }
+
+    /// Finds the track sharing the most time-consistent landmarks with the
+    /// signature, or `None` when no landmark matches at all.
+    pub fn query(&self, signature: &DecodedSignature) -> Option<LandmarkMatch> {
+        self.query_landmarks(&landmarks_from_signature(signature))
+    }
+
+    pub fn query_landmarks(&self, landmarks: &[Landmark]) -> Option<LandmarkMatch> {
+        let mut votes = Votes::new();
+        self.vote(landmarks, &mut votes);
+        best_match(votes, &self.track_ids)
+    }
+
+    pub fn vote(&self, landmarks: &[Landmark], votes: &mut Votes) {
+        for landmark in landmarks {
+            if let Some(postings) = self.postings.get(&landmark.hash) {
+                cast_votes(landmark, postings.iter().copied(), votes);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fingerprinting::signature_format::FrequencyBand;
+
+    fn peak(fft_pass_number: u32, corrected_peak_frequency_bin: u16) -> FrequencyPeak {
+        FrequencyPeak {
+            fft_pass_number,
+            peak_magnitude: 7000,
+            corrected_peak_frequency_bin,
+        }
+    }
+
+    fn signature(peaks: Vec<(FrequencyBand, Vec<FrequencyPeak>)>) -> DecodedSignature {
+        DecodedSignature {
+            sample_rate_hz: 16000,
+            number_samples: 16000 * 20,
+            samples_used: 16000 * 20,
+            frequency_band_to_sound_peaks: peaks.into_iter().collect(),
+            quality: None,
+        }
+    }
+
+    /// Peaks in every band with random bins, a few FFT passes apart.
+    fn random_track(rng: &mut fastrand::Rng, fft_passes: u32) -> DecodedSignature {
+        let bands = [
+            FrequencyBand::_250_520,
+            FrequencyBand::_520_1450,
+            FrequencyBand::_1450_3500,
+            FrequencyBand::_3500_5500,
+        ];
+        signature(
+            bands
+                .iter()
+                .map(|band| {
+                    let mut peaks = vec![];
+                    let mut fft_pass_number = rng.u32(0..8);
+                    while fft_pass_number < fft_passes {
+                        peaks.push(peak(fft_pass_number, rng.u16(..)));
+                        fft_pass_number += rng.u32(1..12);
+                    }
+                    (*band, peaks)
+                })
+                .collect(),
+        )
+    }
+
+    #[test]
+    fn packs_band_bins_and_delta() {
+        // The fractional part of the bins is dropped
+        let anchor = peak(100, (0x3ff << 6) | 0x3f);
+        let target = peak(163, (0x155 << 6) | 0x01);
+        let hash = landmark_hash(FrequencyBand::_3500_5500 as u32, &anchor, &target);
+
+        assert_eq!(hash >> 28, 3);
+        assert_eq!((hash >> 18) & 0x3ff, 0x3ff);
+        assert_eq!((hash >> 8) & 0x3ff, 0x155);
+        assert_eq!(hash & 0xff, 63);
+
+        let swapped = landmark_hash(FrequencyBand::_250_520 as u32, &peak(0, 0x155 << 6), &peak(1, 0x3ff << 6));
+        assert_eq!(swapped, (0x155 << 18) | (0x3ff << 8) | 1);
+    }
+
+    #[test]
+    fn pairs_peaks_within_the_fan_out_and_time_limits() {
+        // Peaks at the same time are never paired
+        let dense = signature(vec![(
+            FrequencyBand::_520_1450,
+            (0..10).map(|index| peak(index / 2 * 2, 640)).collect(),
+        )]);
+        let landmarks = landmarks_from_signature(&dense);
+        let from_first: Vec<_> = landmarks
+            .iter()
+            .filter(|landmark| landmark.anchor_fft_pass_number == 0)
+            .collect();
+        assert_eq!(from_first.len(), 2 * FAN_OUT);
+        assert!(from_first.iter().all(|landmark| landmark.hash & 0xff >= 2));
+
+        let sparse = signature(vec![(
+            FrequencyBand::_520_1450,
+            vec![
+                peak(0, 640),
+                peak(MAX_DELTA_FFT_PASSES, 1280),
+                peak(MAX_DELTA_FFT_PASSES * 2 + 1, 1920),
+            ],
+        )]);
+        let landmarks = landmarks_from_signature(&sparse);
+        assert_eq!(landmarks.len(), 1);
+        assert_eq!(landmarks[0].anchor_fft_pass_number, 0);
+        assert_eq!(landmarks[0].hash & 0xff, MAX_DELTA_FFT_PASSES);
+    }
+
+    #[test]
+    fn finds_an_excerpt_at_its_offset() {
+        let mut rng = fastrand::Rng::with_seed(0x5EED);
+        let tracks: Vec<_> = (0..3).map(|_| random_track(&mut rng, 2500)).collect();
+
+        let mut index = LandmarkIndex::new();
+        for (number, track) in tracks.iter().enumerate() {
+            index.add_signature(&format!("track-{}", number), track);
+        }
+
+        // About 4 seconds of the second track, starting 6 seconds in
+        let (start, end) = (750, 1250);
+        let excerpt = signature(
+            tracks[1]
+                .frequency_band_to_sound_peaks
+                .iter()
+                .map(|(band, peaks)| {
+                    let peaks = peaks
+                        .iter()
+                        .filter(|peak| (start..end).contains(&peak.fft_pass_number))
+                        .map(|found| peak(found.fft_pass_number - start, found.corrected_peak_frequency_bin))
+                        .collect();
+                    (*band, peaks)
+                })
+                .collect(),
+        );
+
+        let found = index.query(&excerpt).unwrap();
+        assert_eq!(found.track_id, "track-1");
+        assert_eq!(found.offset_fft_passes, start as i64);
+        assert!(found.votes as usize > landmarks_from_signature(&excerpt).len() / 2);
+        assert!((found.offset_seconds() - 6.0).abs() < 1e-3);
+    }
+}
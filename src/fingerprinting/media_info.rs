@@ -0,0 +1,238 @@
+use crate::fingerprinting::ffmpeg_wrapper::{run_ffprobe, FFMpegSettings};
+use crate::fingerprinting::shared_bytes::SharedBytes;
+use log::debug;
+use rodio::Source;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::error::Error;
+use std::io::{BufReader, Cursor};
+
+/// Container, codec and tags of a media file, as far as they could be read.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct MediaInfo {
+    pub duration_seconds: Option<f64>,
+    pub container: Option<String>,
+    pub codec: Option<String>,
+    pub sample_rate: Option<u32>,
+    pub channels: Option<u16>,
+    /// Bits per second, estimated from the size and the duration when the
+    /// container does not tell.
+    pub bitrate: Option<u64>,
+    /// Tags with lowercase keys, such as "title" or "artist".
+    pub tags: HashMap<String, String>,
+}
+
+impl MediaInfo {
+    fn estimate_bitrate(&mut self, size: u64) {
+        if let (None, Some(duration_seconds)) = (self.bitrate, self.duration_seconds) {
+            if duration_seconds > 0.0 {
+                self.bitrate = Some((size as f64 * 8.0 / duration_seconds) as u64);
+            }
+        }
+    }
+}
+
+/// FFProbe reports most numbers as strings.
+fn json_number<T: std::str::FromStr>(value: &Value) -> Option<T> {
+    match value {
+        Value::String(string) => string.parse().ok(),
+        Value::Number(number) => number.to_string().parse().ok(),
+        _ => None,
+    }
+}
+
+fn json_tags(value: &Value, tags: &mut HashMap<String, String>) {
+    if let Some(object) = value.as_object() {
+        for (key, value) in object {
+            if let Some(value) = value.as_str() {
+                tags.entry(key.to_lowercase())
+                    .or_insert_with(|| value.to_string());
+            }
+        }
+    }
+}
+
+/// Reads the output of FFProbe, for the first audio stream.
+fn media_info_from_ffprobe(description: &Value) -> Result<MediaInfo, Box<dyn Error>> {
+    let format = &description["format"];
+    let stream = description["streams"]
+        .as_array()
+        .and_then(|streams| {
+            streams
+                .iter()
+                .find(|stream| stream["codec_type"].as_str() == Some("audio"))
+        })
+        .ok_or("No audio stream found")?;
+
+    let mut info = MediaInfo {
+        duration_seconds: json_number(&format["duration"]).or_else(|| json_number(&stream["duration"])),
+        container: format["format_name"].as_str().map(String::from),
+        codec: stream["codec_name"].as_str().map(String::from),
+        sample_rate: json_number(&stream["sample_rate"]),
+        channels: json_number(&stream["channels"]),
+        bitrate: json_number(&format["bit_rate"]).or_else(|| json_number(&stream["bit_rate"])),
+        tags: HashMap::new(),
+    };
+
+    // Vorbis comments are found on the stream, ID3 and MP4 tags on the
+    // container
+
+    json_tags(&format["tags"], &mut info.tags);
+    json_tags(&stream["tags"], &mut info.tags);
+
+    if let Some(size) = json_number(&format["size"]) {
+        info.estimate_bitrate(size);
+    }
+
+    Ok(info)
+}
+
+/// Reads what Symphonia can tell about the input, including its tags.
+#[cfg(feature = "symphonia")]
+fn media_info_from_symphonia(
+    source: Box<dyn symphonia::core::io::MediaSource>,
+    extension: Option<&str>,
+) -> Result<MediaInfo, Box<dyn Error>> {
+    use symphonia::core::codecs::CODEC_TYPE_NULL;
+    use symphonia::core::io::MediaSourceStream;
+    use symphonia::core::meta::StandardTagKey;
+    use symphonia::core::probe::Hint;
+
+    let mut hint = Hint::new();
+    if let Some(extension) = extension {
+        hint.with_extension(extension);
+    }
+
+    let mut probed = symphonia::default::get_probe().format(
+        &hint,
+        MediaSourceStream::new(source, Default::default()),
+        &Default::default(),
+        &Default::default(),
+    )?;
+
+    let track = probed
+        .format
+        .tracks()
+        .iter()
+        .find(|track| track.codec_params.codec != CODEC_TYPE_NULL)
+        .ok_or("No audio track found")?;
+    let codec_params = &track.codec_params;
+
+    let mut info = MediaInfo {
+        duration_seconds: codec_params
+            .n_frames
+            .zip(codec_params.sample_rate)
+            .map(|(frames, sample_rate)| frames as f64 / sample_rate as f64),
+        container: None,
+        codec: symphonia::default::get_codecs()
+            .get_codec(codec_params.codec)
+            .map(|codec| codec.short_name.to_string()),
+        sample_rate: codec_params.sample_rate,
+        channels: codec_params.channels.map(|channels| channels.count() as u16),
+        bitrate: None,
+        tags: HashMap::new(),
+    };
+
+    // Tags found before the container (ID3) come first
+
+    let mut revisions = vec![];
+    if let Some(metadata) = probed.metadata.get() {
+        revisions.extend(metadata.current().cloned());
+    }
+    revisions.extend(probed.format.metadata().current().cloned());
+
+    for revision in revisions {
+        for tag in revision.tags() {
+            // Name the common tags the way FFProbe does
+            let key = match tag.std_key {
+                Some(StandardTagKey::TrackTitle) => "title".to_string(),
+                Some(StandardTagKey::Artist) => "artist".to_string(),
+                Some(StandardTagKey::Album) => "album".to_string(),
+                Some(StandardTagKey::AlbumArtist) => "album_artist".to_string(),
+                Some(StandardTagKey::Composer) => "composer".to_string(),
+                Some(StandardTagKey::Genre) => "genre".to_string(),
+                Some(StandardTagKey::Date) => "date".to_string(),
+                Some(StandardTagKey::TrackNumber) => "track".to_string(),
+                Some(StandardTagKey::Comment) => "comment".to_string(),
+                _ => tag.key.to_lowercase(),
+            };
+            info.tags.entry(key).or_insert_with(|| tag.value.to_string());
+        }
+    }
+
+    Ok(info)
+}
+
+/// Reads the format of the input with Rodio, which only tells the sample
+/// rate, the channels and sometimes the duration.
+fn media_info_from_rodio<S: Source<Item = i16>>(source: S) -> MediaInfo {
+    MediaInfo {
+        duration_seconds: source.total_duration().map(|duration| duration.as_secs_f64()),
+        sample_rate: Some(source.sample_rate()),
+        channels: Some(source.channels()),
+        ..Default::default()
+    }
+}
+
+/// Describes a media file with FFProbe when it is available, falling back
+/// to Symphonia when enabled, and to Rodio.
+pub fn probe_file(file_path: &str, ffmpeg: &FFMpegSettings) -> Result<MediaInfo, Box<dyn Error>> {
+    match run_ffprobe(file_path, None, ffmpeg).and_then(|description| media_info_from_ffprobe(&description)) {
+        Ok(info) => return Ok(info),
+        Err(error) => debug!("FFprobe failed to describe {}: {}", file_path, error),
+    }
+
+    let size = std::fs::metadata(file_path)?.len();
+
+    #[cfg(feature = "symphonia")]
+    {
+        let extension = std::path::Path::new(file_path)
+            .extension()
+            .and_then(|extension| extension.to_str());
+
+        match media_info_from_symphonia(Box::new(std::fs::File::open(file_path)?), extension) {
+            Ok(mut info) => {
+                info.estimate_bitrate(size);
+                return Ok(info);
+            }
+            Err(error) => debug!("Symphonia failed to describe {}: {}", file_path, error),
+        }
+    }
+
+    let decoder = rodio::Decoder::new(BufReader::new(std::fs::File::open(file_path)?))?;
+    let mut info = media_info_from_rodio(decoder);
+    info.estimate_bitrate(size);
+
+    Ok(info)
+}
+
+/// Describes an in-memory media file, like `probe_file`.
+pub fn probe_bytes(bytes: SharedBytes, ffmpeg: &FFMpegSettings) -> Result<MediaInfo, Box<dyn Error>> {
+    match run_ffprobe("pipe:0", Some(bytes.clone()), ffmpeg)
+        .and_then(|description| media_info_from_ffprobe(&description))
+    {
+        Ok(mut info) => {
+            // Piped inputs have no size for FFProbe
+            info.estimate_bitrate(bytes.len() as u64);
+            return Ok(info);
+        }
+        Err(error) => debug!("FFprobe failed to describe bytes: {}", error),
+    }
+
+    let size = bytes.len() as u64;
+
+    #[cfg(feature = "symphonia")]
+    match media_info_from_symphonia(Box::new(Cursor::new(bytes.clone())), None) {
+        Ok(mut info) => {
+            info.estimate_bitrate(size);
+            return Ok(info);
+        }
+        Err(error) => debug!("Symphonia failed to describe bytes: {}", error),
+    }
+
+    let decoder = rodio::Decoder::new(Cursor::new(bytes))?;
+    let mut info = media_info_from_rodio(decoder);
+    info.estimate_bitrate(size);
+
+    Ok(info)
+}
@@ -1,5 +1,21 @@
 pub mod algorithm;
+pub mod alignment;
+pub mod audio_format;
+pub mod audio_stream;
+#[cfg(feature = "client")]
+pub mod client;
 pub mod communication;
+pub mod database;
+pub mod decoder;
+pub mod duplicates;
+pub mod errors;
 pub mod ffmpeg_wrapper;
 pub mod hanning;
+pub mod landmark_index;
+pub mod media_info;
+pub mod pcm;
+pub mod quality;
+pub mod shared_bytes;
 pub mod signature_format;
+#[cfg(feature = "symphonia")]
+pub mod symphonia_decoder;
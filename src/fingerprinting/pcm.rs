@@ -0,0 +1,98 @@
+use crate::fingerprinting::errors::FingerprintError;
+
+/// Format of the samples of raw PCM audio, in native byte order.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SampleFormat {
+    I16,
+    I32,
+    F32,
+}
+
+impl SampleFormat {
+    /// Parses the name of a format: "i16", "i32" or "f32".
+    pub fn parse(name: &str) -> Result<Self, FingerprintError> {
+        match name.to_ascii_lowercase().as_str() {
+            "i16" => Ok(SampleFormat::I16),
+            "i32" => Ok(SampleFormat::I32),
+            "f32" => Ok(SampleFormat::F32),
+            _ => Err(FingerprintError::InvalidPcm(format!(
+                "unknown sample format {:?}, expected \"i16\", \"i32\" or \"f32\"",
+                name
+            ))),
+        }
+    }
+
+    /// Name of the format, as parsed.
+    pub fn name(&self) -> &'static str {
+        match self {
+            SampleFormat::I16 => "i16",
+            SampleFormat::I32 => "i32",
+            SampleFormat::F32 => "f32",
+        }
+    }
+
+    /// Size of a sample in bytes.
+    pub fn sample_size(&self) -> usize {
+        match self {
+            SampleFormat::I16 => 2,
+            SampleFormat::I32 | SampleFormat::F32 => 4,
+        }
+    }
+}
+
+/// Converts a 32-bit integer sample to 16-bit, keeping the most significant
+/// bits.
+pub fn i32_to_i16(sample: i32) -> i16 {
+    (sample >> 16) as i16
+}
+
+/// Converts a floating point sample, nominally between -1.0 and 1.0, to
+/// 16-bit. Louder samples are clipped.
+pub fn f32_to_i16(sample: f32) -> i16 {
+    (sample.clamp(-1.0, 1.0) * i16::MAX as f32) as i16
+}
+
+/// Reads samples of the given format from raw bytes in native byte order,
+/// and converts them to 16-bit.
+pub fn bytes_to_i16(bytes: &[u8], format: SampleFormat) -> Result<Vec<i16>, FingerprintError> {
+    if bytes.len() % format.sample_size() != 0 {
+        return Err(FingerprintError::InvalidPcm(format!(
+            "{} bytes is not a whole number of {} samples",
+            bytes.len(),
+            format.name()
+        )));
+    }
+
+    Ok(match format {
+        SampleFormat::I16 => bytes
+            .chunks_exact(2)
+            .map(|sample| i16::from_ne_bytes([sample[0], sample[1]]))
+            .collect(),
+        SampleFormat::I32 => bytes
+            .chunks_exact(4)
+            .map(|sample| i32_to_i16(i32::from_ne_bytes([sample[0], sample[1], sample[2], sample[3]])))
+            .collect(),
+        SampleFormat::F32 => bytes
+            .chunks_exact(4)
+            .map(|sample| f32_to_i16(f32::from_ne_bytes([sample[0], sample[1], sample[2], sample[3]])))
+            .collect(),
+    })
+}
+
+/// Checks that interleaved samples can be played at the given rate and
+/// number of channels.
+pub fn check_pcm_layout(samples: usize, sample_rate: u32, channels: u16) -> Result<(), FingerprintError> {
+    if sample_rate == 0 {
+        return Err(FingerprintError::InvalidPcm("the sample rate must be positive".into()));
+    }
+    if channels == 0 {
+        return Err(FingerprintError::InvalidPcm("there must be at least one channel".into()));
+    }
+    if samples % channels as usize != 0 {
+        return Err(FingerprintError::InvalidPcm(format!(
+            "{} samples cannot be split into {} interleaved channels",
+            samples, channels
+        )));
+    }
+    Ok(())
+}
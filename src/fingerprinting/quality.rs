@@ -0,0 +1,331 @@
+use crate::fingerprinting::signature_format::{DecodedSignature, FrequencyBand};
+
+/// Frames (128 samples) quieter than this are counted as silence.
+const SILENCE_THRESHOLD_DBFS: f32 = -60.0;
+
+/// The effective bandwidth ends at the highest frequency whose average
+/// power is within this distance of the strongest frequency.
+const BANDWIDTH_DYNAMIC_RANGE_DB: f32 = 50.0;
+
+const MIN_PEAKS_PER_SECOND: f32 = 10.0;
+const MAX_SILENCE_RATIO: f32 = 0.5;
+const MAX_CLIPPED_RATIO: f32 = 0.01;
+const MIN_RMS_DBFS: f32 = -45.0;
+const MIN_BANDWIDTH_HZ: f32 = 2000.0;
+const MIN_DURATION_SECONDS: f32 = 3.0;
+
+pub const FREQUENCY_BANDS: [FrequencyBand; 4] = [
+    FrequencyBand::_250_520,
+    FrequencyBand::_520_1450,
+    FrequencyBand::_1450_3500,
+    FrequencyBand::_3500_5500,
+];
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct QualityReport {
+    pub duration_seconds: f32,
+    /// Peaks per second for each of `FREQUENCY_BANDS`, in the same order.
+    pub peaks_per_second: [f32; 4],
+    pub rms_dbfs: f32,
+    pub peak_dbfs: f32,
+    pub clipped_ratio: f32,
+    pub silence_ratio: f32,
+    pub effective_bandwidth_hz: f32,
+    pub likely_unrecognizable: bool,
+    pub reasons: Vec<String>,
+}
+
+fn to_dbfs(amplitude: f64) -> f32 {
+    (20.0 * (amplitude / 32768.0).max(1e-10).log10()) as f32
+}
+
+/// Counts the samples at full scale. Decoded audio is counted before it is
+/// downmixed and resampled, which would smooth clipped samples out.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct ClippingCounter {
+    samples: u64,
+    clipped_samples: u64,
+}
+
+impl ClippingCounter {
+    pub fn add_sample(&mut self, sample: i16) {
+        self.samples += 1;
+        if sample == i16::MAX || sample == i16::MIN {
+            self.clipped_samples += 1;
+        }
+    }
+
+    fn ratio(&self) -> f32 {
+        (self.clipped_samples as f64 / self.samples.max(1) as f64) as f32
+    }
+}
+
+/// Accumulates level and spectrum statistics while a signature is being
+/// generated, to tell whether it is likely to be recognized.
+pub struct QualityAnalyzer {
+    samples: usize,
+    sum_of_squares: f64,
+    peak_amplitude: i32,
+    clipping: ClippingCounter,
+    /// Clipping of the audio as it was decoded, when it was converted to
+    /// 16 KHz mono before being analyzed.
+    decoded_clipping: Option<ClippingCounter>,
+    frames: usize,
+    silent_frames: usize,
+    spectrum_sum: Vec<f64>,
+    spectrum_count: usize,
+}
+
+impl Default for QualityAnalyzer {
+    fn default() -> Self {
+        QualityAnalyzer::new()
+    }
+}
+
+impl QualityAnalyzer {
+    pub fn new() -> Self {
+        QualityAnalyzer {
+            samples: 0,
+            sum_of_squares: 0.0,
+            peak_amplitude: 0,
+            clipping: ClippingCounter::default(),
+            decoded_clipping: None,
+            frames: 0,
+            silent_frames: 0,
+            spectrum_sum: vec![0.0; 1025],
+            spectrum_count: 0,
+        }
+    }
+
+    pub fn add_samples(&mut self, s16_mono_16khz_buffer: &[i16]) {
+        for frame in s16_mono_16khz_buffer.chunks(128) {
+            let mut frame_sum_of_squares = 0.0f64;
+
+            for sample in frame {
+                let amplitude = (*sample as i32).abs();
+                frame_sum_of_squares += (amplitude as f64).powi(2);
+                self.peak_amplitude = self.peak_amplitude.max(amplitude);
+                self.clipping.add_sample(*sample);
+            }
+
+            if to_dbfs((frame_sum_of_squares / frame.len() as f64).sqrt()) < SILENCE_THRESHOLD_DBFS {
+                self.silent_frames += 1;
+            }
+
+            self.frames += 1;
+            self.samples += frame.len();
+            self.sum_of_squares += frame_sum_of_squares;
+        }
+    }
+
+    /// Measures clipping on the audio as it was decoded rather than on the
+    /// samples added, once they were converted from it.
+    pub fn set_decoded_clipping(&mut self, clipping: ClippingCounter) {
+        self.decoded_clipping = Some(clipping);
+    }
+
+    /// Adds the power spectrum (1025 bins) of one FFT pass.
+    pub fn add_spectrum(&mut self, power_spectrum: &[f32]) {
+        for (sum, power) in self.spectrum_sum.iter_mut().zip(power_spectrum) {
+            *sum += *power as f64;
+        }
+        self.spectrum_count += 1;
+    }
+
+    fn effective_bandwidth_hz(&self) -> f32 {
+        if self.spectrum_count == 0 {
+            return 0.0;
+        }
+
+        // Skip the DC offset and the lowest bins, which hold no useful signal
+
+        let strongest = self.spectrum_sum[4..]
+            .iter()
+            .cloned()
+            .fold(0.0f64, f64::max);
+
+        // FFT outputs are clamped to 1e-10, digital silence stays at that level
+
+        if strongest <= self.spectrum_count as f64 * 0.0000000001 * 1.001 {
+            return 0.0;
+        }
+
+        let floor = strongest * 10f64.powf(-(BANDWIDTH_DYNAMIC_RANGE_DB as f64) / 10.0);
+        let highest_bin = self
+            .spectrum_sum
+            .iter()
+            .rposition(|power| *power >= floor)
+            .unwrap_or(0);
+
+        highest_bin as f32 * 16000.0 / 2048.0
+    }
+
+    pub fn finish(&self, signature: &DecodedSignature) -> QualityReport {
+        let duration_seconds = self.samples as f32 / 16000.0;
+        let samples = self.samples.max(1) as f64;
+
+        let mut peaks_per_second = [0.0f32; 4];
+        for (index, frequency_band) in FREQUENCY_BANDS.iter().enumerate() {
+            let peaks = signature
+                .frequency_band_to_sound_peaks
+                .get(frequency_band)
+                .map_or(0, |peaks| peaks.len());
+            peaks_per_second[index] = peaks as f32 / duration_seconds.max(f32::EPSILON);
+        }
+
+        let rms_dbfs = to_dbfs((self.sum_of_squares / samples).sqrt());
+        let peak_dbfs = to_dbfs(self.peak_amplitude as f64);
+        let clipped_ratio = self.decoded_clipping.unwrap_or(self.clipping).ratio();
+        let silence_ratio = self.silent_frames as f32 / self.frames.max(1) as f32;
+        let effective_bandwidth_hz = self.effective_bandwidth_hz();
+
+        let mut reasons = vec![];
+        let total_peaks_per_second: f32 = peaks_per_second.iter().sum();
+
+        if duration_seconds < MIN_DURATION_SECONDS {
+            reasons.push(format!(
+                "only {:.1} s of audio, at least {:.0} s are recommended",
+                duration_seconds, MIN_DURATION_SECONDS
+            ));
+        }
+        if total_peaks_per_second < MIN_PEAKS_PER_SECOND {
+            reasons.push(format!(
+                "only {:.1} peaks per second were found",
+                total_peaks_per_second
+            ));
+        }
+        if silence_ratio > MAX_SILENCE_RATIO {
+            reasons.push(format!("{:.0}% of the audio is silent", silence_ratio * 100.0));
+        }
+        if rms_dbfs < MIN_RMS_DBFS {
+            reasons.push(format!("signal level is very low ({:.1} dBFS RMS)", rms_dbfs));
+        }
+        if clipped_ratio > MAX_CLIPPED_RATIO {
+            reasons.push(format!(
+                "{:.1}% of the samples are clipped",
+                clipped_ratio * 100.0
+            ));
+        }
+        if effective_bandwidth_hz < MIN_BANDWIDTH_HZ {
+            reasons.push(format!(
+                "effective bandwidth is only {:.0} Hz",
+                effective_bandwidth_hz
+            ));
+        }
+
+        QualityReport {
+            duration_seconds,
+            peaks_per_second,
+            rms_dbfs,
+            peak_dbfs,
+            clipped_ratio,
+            silence_ratio,
+            effective_bandwidth_hz,
+            likely_unrecognizable: !reasons.is_empty(),
+            reasons,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fingerprinting::algorithm::SignatureGenerator;
+    use crate::params::SearchParams;
+
+    /// Ten seconds of random tones between the given frequencies, changing
+    /// every quarter second without phase jumps, with each sample scaled by
+    /// `gain` and clamped to 16 bits.
+    fn random_tones(
+        rng: &mut fastrand::Rng,
+        frequencies: std::ops::Range<f32>,
+        sample_rate: u32,
+        channels: u16,
+        gain: f32,
+    ) -> Vec<i16> {
+        let mut samples = vec![];
+        let mut phases = [0.0f32; 3];
+        for _ in 0..40 {
+            let tones: Vec<f32> = (0..3)
+                .map(|_| frequencies.start + rng.f32() * (frequencies.end - frequencies.start))
+                .collect();
+            for _ in 0..sample_rate / 4 {
+                let mut sample = 0.0;
+                for (phase, frequency) in phases.iter_mut().zip(&tones) {
+                    *phase += std::f32::consts::TAU * frequency / sample_rate as f32;
+                    *phase %= std::f32::consts::TAU;
+                    sample += phase.sin() * 6000.0;
+                }
+                for _ in 0..channels {
+                    samples.push((sample * gain).clamp(-32768.0, 32767.0) as i16);
+                }
+            }
+        }
+        samples
+    }
+
+    fn report(samples: Vec<i16>, sample_rate: u32, channels: u16) -> QualityReport {
+        let signature = SignatureGenerator::make_signature_from_interleaved_pcm(
+            samples,
+            sample_rate,
+            channels,
+            &SearchParams::default(),
+        )
+        .unwrap();
+        signature.quality.unwrap()
+    }
+
+    fn has_reason(report: &QualityReport, reason: &str) -> bool {
+        report.reasons.iter().any(|found| found.contains(reason))
+    }
+
+    #[test]
+    fn accepts_a_clean_broadband_signal() {
+        let mut rng = fastrand::Rng::with_seed(0x5EED);
+        let report = report(random_tones(&mut rng, 300.0..5000.0, 16000, 1, 1.0), 16000, 1);
+
+        assert!(!report.likely_unrecognizable, "{:?}", report.reasons);
+        assert_eq!(report.clipped_ratio, 0.0);
+    }
+
+    #[test]
+    fn measures_clipping_before_resampling() {
+        let mut rng = fastrand::Rng::with_seed(0x5EED);
+        let samples = random_tones(&mut rng, 300.0..5000.0, 44100, 2, 4.0);
+        let clipped = samples
+            .iter()
+            .filter(|sample| **sample == i16::MAX || **sample == i16::MIN)
+            .count() as f32
+            / samples.len() as f32;
+        assert!(clipped > 0.1);
+
+        let report = report(samples, 44100, 2);
+        assert!(report.likely_unrecognizable);
+        assert!(has_reason(&report, "clipped"), "{:?}", report.reasons);
+        assert!((report.clipped_ratio - clipped).abs() < 0.01, "{} != {}", report.clipped_ratio, clipped);
+    }
+
+    #[test]
+    fn rejects_silence() {
+        let report = report(vec![0; 16000 * 10], 16000, 1);
+
+        assert!(report.likely_unrecognizable);
+        assert_eq!(report.silence_ratio, 1.0);
+        assert_eq!(report.effective_bandwidth_hz, 0.0);
+        for reason in ["silent", "signal level is very low", "peaks per second", "bandwidth"] {
+            assert!(has_reason(&report, reason), "{:?}", report.reasons);
+        }
+        assert!(!has_reason(&report, "clipped"));
+    }
+
+    #[test]
+    fn rejects_band_limited_audio() {
+        let mut rng = fastrand::Rng::with_seed(0x5EED);
+        let report = report(random_tones(&mut rng, 300.0..900.0, 16000, 1, 1.0), 16000, 1);
+
+        assert!(report.likely_unrecognizable, "{:?}", report);
+        assert!(report.effective_bandwidth_hz < MIN_BANDWIDTH_HZ);
+        assert!(has_reason(&report, "bandwidth"), "{:?}", report.reasons);
+        assert!(!has_reason(&report, "silent") && !has_reason(&report, "clipped"));
+    }
+}
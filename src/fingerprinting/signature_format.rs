@@ -1,14 +1,17 @@
+use crate::fingerprinting::errors::FingerprintError;
+use crate::fingerprinting::quality::QualityReport;
 use base64::engine::general_purpose;
 use base64::Engine;
-use byteorder::{LittleEndian, WriteBytesExt};
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
 use crc32fast::Hasher;
 use std::cmp::Ordering;
 use std::collections::HashMap;
 use std::error::Error;
-use std::io::{Cursor, Seek, SeekFrom, Write};
+use std::io::{Cursor, Read, Seek, SeekFrom, Write};
 
 const DATA_URI_PREFIX: &str = "data:audio/vnd.shazam.sig;base64,";
 
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub struct FrequencyPeak {
     pub fft_pass_number: u32,
     pub peak_magnitude: u16,
@@ -23,6 +26,18 @@ pub enum FrequencyBand {
     _3500_5500 = 3,
 }
 
+impl FrequencyBand {
+    pub fn from_index(index: u32) -> Option<FrequencyBand> {
+        match index {
+            0 => Some(FrequencyBand::_250_520),
+            1 => Some(FrequencyBand::_520_1450),
+            2 => Some(FrequencyBand::_1450_3500),
+            3 => Some(FrequencyBand::_3500_5500),
+            _ => None,
+        }
+    }
+}
+
 impl Ord for FrequencyBand {
     fn cmp(&self, other: &Self) -> Ordering {
         (*self as i32).cmp(&(*other as i32))
@@ -31,17 +46,148 @@ impl Ord for FrequencyBand {
 
 impl PartialOrd for FrequencyBand {
     fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
-        Some((*self as i32).cmp(&(*other as i32)))
+        Some(self.cmp(other))
     }
 }
 
 pub struct DecodedSignature {
     pub sample_rate_hz: u32,
     pub number_samples: u32,
+    pub samples_used: u32,
     pub frequency_band_to_sound_peaks: HashMap<FrequencyBand, Vec<FrequencyPeak>>,
+    /// Only available for signatures generated from audio.
+    pub quality: Option<QualityReport>,
 }
 
 impl DecodedSignature {
+    /// Parses the binary format written by `encode_to_binary`, so that
+    /// signatures handed back by Python can be reused (e.g. for indexing).
+    pub fn decode_from_binary(data: &[u8]) -> Result<DecodedSignature, Box<dyn Error>> {
+        if data.len() < 56 {
+            return Err(Box::new(FingerprintError::MalformedSignature(
+                "signature is shorter than its header",
+            )));
+        }
+
+        let mut cursor = Cursor::new(data);
+
+        let magic1 = cursor.read_u32::<LittleEndian>()?;
+        let crc32 = cursor.read_u32::<LittleEndian>()?;
+        let size_minus_header = cursor.read_u32::<LittleEndian>()?;
+        let magic2 = cursor.read_u32::<LittleEndian>()?;
+
+        if magic1 != 0xcafe2580 || magic2 != 0x94119c00 {
+            return Err(Box::new(FingerprintError::MalformedSignature(
+                "invalid magic numbers",
+            )));
+        }
+        if size_minus_header as usize != data.len() - 48 {
+            return Err(Box::new(FingerprintError::MalformedSignature(
+                "size field does not match the data length",
+            )));
+        }
+
+        let mut hasher = Hasher::new();
+        hasher.update(&data[8..]);
+        if hasher.finalize() != crc32 {
+            return Err(Box::new(FingerprintError::MalformedSignature(
+                "CRC32 checksum mismatch",
+            )));
+        }
+
+        cursor.seek(SeekFrom::Start(28))?;
+        let sample_rate_hz = match cursor.read_u32::<LittleEndian>()? >> 27 {
+            1 => 8000,
+            2 => 11025,
+            3 => 16000,
+            4 => 32000,
+            5 => 44100,
+            6 => 48000,
+            _ => {
+                return Err(Box::new(FingerprintError::MalformedSignature(
+                    "unknown sample rate identifier",
+                )));
+            }
+        };
+
+        cursor.seek(SeekFrom::Start(40))?;
+        let number_samples = cursor
+            .read_u32::<LittleEndian>()?
+            .saturating_sub((sample_rate_hz as f32 * 0.24) as u32);
+
+        // Skip the fixed value and the 0x40000000 tag with its size
+
+        cursor.seek(SeekFrom::Start(56))?;
+
+        let mut frequency_band_to_sound_peaks = HashMap::new();
+
+        while (cursor.position() as usize) < data.len() {
+            let tag = cursor.read_u32::<LittleEndian>()?;
+            let size = cursor.read_u32::<LittleEndian>()? as usize;
+
+            let frequency_band = tag
+                .checked_sub(0x60030040)
+                .and_then(FrequencyBand::from_index)
+                .ok_or(FingerprintError::MalformedSignature(
+                    "unknown frequency band tag",
+                ))?;
+
+            // The size comes from the input, check it before allocating
+            if size > data.len() - cursor.position() as usize {
+                return Err(Box::new(FingerprintError::MalformedSignature(
+                    "frequency band is longer than the signature",
+                )));
+            }
+
+            let mut peaks_buffer = vec![0u8; size];
+            cursor.read_exact(&mut peaks_buffer)?;
+            cursor.seek(SeekFrom::Current(((4 - size % 4) % 4) as i64))?;
+
+            let mut peaks_cursor = Cursor::new(peaks_buffer);
+            let mut frequency_peaks = vec![];
+            let mut fft_pass_number = 0u32;
+
+            while (peaks_cursor.position() as usize) < size {
+                let fft_pass_offset = peaks_cursor.read_u8()?;
+
+                if fft_pass_offset == 0xff {
+                    fft_pass_number = peaks_cursor.read_u32::<LittleEndian>()?;
+                    continue;
+                }
+
+                fft_pass_number = fft_pass_number
+                    .checked_add(fft_pass_offset as u32)
+                    .ok_or(FingerprintError::MalformedSignature(
+                        "FFT pass number overflows",
+                    ))?;
+
+                frequency_peaks.push(FrequencyPeak {
+                    fft_pass_number,
+                    peak_magnitude: peaks_cursor.read_u16::<LittleEndian>()?,
+                    corrected_peak_frequency_bin: peaks_cursor.read_u16::<LittleEndian>()?,
+                });
+            }
+
+            frequency_band_to_sound_peaks.insert(frequency_band, frequency_peaks);
+        }
+
+        Ok(DecodedSignature {
+            sample_rate_hz,
+            number_samples,
+            samples_used: number_samples,
+            frequency_band_to_sound_peaks,
+            quality: None,
+        })
+    }
+
+    pub fn decode_from_uri(uri: &str) -> Result<DecodedSignature, Box<dyn Error>> {
+        let encoded = uri.strip_prefix(DATA_URI_PREFIX).ok_or(
+            FingerprintError::MalformedSignature("URI does not hold a Shazam signature"),
+        )?;
+
+        DecodedSignature::decode_from_binary(&general_purpose::STANDARD.decode(encoded)?)
+    }
+
     pub fn encode_to_binary(&self) -> Result<Vec<u8>, Box<dyn Error>> {
         let mut cursor = Cursor::new(vec![]);
 
@@ -64,14 +210,17 @@ impl DecodedSignature {
                 44100 => 5,
                 48000 => 6,
                 _ => {
-                    panic!("Invalid sample rate passed when encoding Shazam packet");
+                    return Err(Box::new(FingerprintError::UnsupportedSampleRate(
+                        self.sample_rate_hz,
+                    )));
                 }
             } << 27,
         )?; // shifted_sample_rate_id
         cursor.write_u32::<LittleEndian>(0)?; // void2
         cursor.write_u32::<LittleEndian>(0)?;
         cursor.write_u32::<LittleEndian>(
-            self.number_samples + (self.sample_rate_hz as f32 * 0.24) as u32,
+            self.number_samples
+                .saturating_add((self.sample_rate_hz as f32 * 0.24) as u32),
         )?; // number_samples_plus_divided_sample_rate
         cursor.write_u32::<LittleEndian>((15 << 19) + 0x40000)?; // fixed_value
 
@@ -87,7 +236,13 @@ impl DecodedSignature {
             let mut fft_pass_number = 0;
 
             for frequency_peak in frequency_peaks {
-                assert!(frequency_peak.fft_pass_number >= fft_pass_number);
+                if frequency_peak.fft_pass_number < fft_pass_number {
+                    return Err(Box::new(FingerprintError::UnorderedPeaks {
+                        frequency_band: *frequency_band,
+                        previous_fft_pass_number: fft_pass_number,
+                        fft_pass_number: frequency_peak.fft_pass_number,
+                    }));
+                }
 
                 if frequency_peak.fft_pass_number - fft_pass_number >= 255 {
                     peaks_cursor.write_u8(0xff)?;
@@ -140,3 +295,108 @@ impl DecodedSignature {
     }
 
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn signature_with_peaks(peaks: Vec<FrequencyPeak>) -> DecodedSignature {
+        DecodedSignature {
+            sample_rate_hz: 16000,
+            number_samples: 48000,
+            samples_used: 48000,
+            frequency_band_to_sound_peaks: HashMap::from([(FrequencyBand::_520_1450, peaks)]),
+            quality: None,
+        }
+    }
+
+    /// Replaces the content of the only frequency band of an encoded
+    /// signature, keeping the size and checksum fields consistent.
+    fn with_band(encoded: &[u8], declared_size: u32, payload: &[u8]) -> Vec<u8> {
+        let mut data = encoded[..56].to_vec();
+        data.extend_from_slice(&(0x60030040u32 + FrequencyBand::_520_1450 as u32).to_le_bytes());
+        data.extend_from_slice(&declared_size.to_le_bytes());
+        data.extend_from_slice(payload);
+
+        let size_minus_header = (data.len() - 48) as u32;
+        data[8..12].copy_from_slice(&size_minus_header.to_le_bytes());
+        data[52..56].copy_from_slice(&size_minus_header.to_le_bytes());
+        let mut hasher = Hasher::new();
+        hasher.update(&data[8..]);
+        let crc32 = hasher.finalize();
+        data[4..8].copy_from_slice(&crc32.to_le_bytes());
+        data
+    }
+
+    fn malformed_reason(result: Result<DecodedSignature, Box<dyn Error>>) -> &'static str {
+        match result {
+            Err(error) => match error.downcast_ref::<FingerprintError>() {
+                Some(FingerprintError::MalformedSignature(reason)) => reason,
+                _ => panic!("expected a malformed signature, got {}", error),
+            },
+            Ok(_) => panic!("malformed signature was decoded"),
+        }
+    }
+
+    #[test]
+    fn round_trips_through_binary() {
+        let peaks = vec![
+            FrequencyPeak { fft_pass_number: 3, peak_magnitude: 7000, corrected_peak_frequency_bin: 9000 },
+            FrequencyPeak { fft_pass_number: 400, peak_magnitude: 7100, corrected_peak_frequency_bin: 9100 },
+            FrequencyPeak { fft_pass_number: 410, peak_magnitude: 7200, corrected_peak_frequency_bin: 9200 },
+        ];
+        let encoded = signature_with_peaks(peaks.clone()).encode_to_binary().unwrap();
+
+        let decoded = DecodedSignature::decode_from_binary(&encoded).unwrap();
+        assert_eq!(decoded.sample_rate_hz, 16000);
+        assert_eq!(decoded.number_samples, 48000);
+        assert_eq!(decoded.frequency_band_to_sound_peaks[&FrequencyBand::_520_1450], peaks);
+    }
+
+    #[test]
+    fn rejects_band_sizes_beyond_the_data() {
+        let encoded = signature_with_peaks(vec![]).encode_to_binary().unwrap();
+
+        let data = with_band(&encoded, u32::MAX, &[0; 8]);
+        assert_eq!(
+            malformed_reason(DecodedSignature::decode_from_binary(&data)),
+            "frequency band is longer than the signature"
+        );
+    }
+
+    #[test]
+    fn rejects_overflowing_fft_pass_numbers() {
+        let encoded = signature_with_peaks(vec![]).encode_to_binary().unwrap();
+
+        let mut payload = vec![0xff];
+        payload.extend_from_slice(&(u32::MAX - 1).to_le_bytes());
+        payload.extend_from_slice(&[0x10, 0, 0, 0, 0]);
+        payload.extend_from_slice(&[0; 2]);
+        let data = with_band(&encoded, 10, &payload);
+
+        assert_eq!(
+            malformed_reason(DecodedSignature::decode_from_binary(&data)),
+            "FFT pass number overflows"
+        );
+    }
+
+    #[test]
+    fn truncated_or_random_data_never_panics() {
+        let encoded = signature_with_peaks(vec![
+            FrequencyPeak { fft_pass_number: 3, peak_magnitude: 7000, corrected_peak_frequency_bin: 9000 },
+        ])
+        .encode_to_binary()
+        .unwrap();
+
+        for length in 0..encoded.len() {
+            assert!(DecodedSignature::decode_from_binary(&encoded[..length]).is_err());
+        }
+
+        let mut rng = fastrand::Rng::with_seed(0x5EED);
+        for _ in 0..1000 {
+            let mut payload = vec![0u8; rng.usize(0..64)];
+            rng.fill(&mut payload);
+            let _ = DecodedSignature::decode_from_binary(&with_band(&encoded, rng.u32(..64), &payload));
+        }
+    }
+}
@@ -0,0 +1,289 @@
+use crate::fingerprinting::decoder::{DecodeOptions, DecodedAudio};
+use crate::fingerprinting::errors::FingerprintError;
+use std::error::Error;
+use std::time::Duration;
+use symphonia::core::audio::{SampleBuffer, SignalSpec};
+use symphonia::core::codecs::{Decoder, DecoderOptions, CODEC_TYPE_NULL, CODEC_TYPE_OPUS};
+use symphonia::core::errors::Error as SymphoniaError;
+use symphonia::core::formats::{FormatOptions, FormatReader, SeekMode, SeekTo, Track};
+use symphonia::core::io::{MediaSource, MediaSourceStream};
+use symphonia::core::meta::MetadataOptions;
+use symphonia::core::probe::Hint;
+use symphonia::core::units::Time;
+
+fn open_format(
+    source: Box<dyn MediaSource>,
+    extension: Option<&str>,
+) -> Result<Box<dyn FormatReader>, Box<dyn Error>> {
+    let media_source_stream = MediaSourceStream::new(source, Default::default());
+
+    let mut hint = Hint::new();
+    if let Some(extension) = extension {
+        hint.with_extension(extension);
+    }
+
+    let probed = symphonia::default::get_probe().format(
+        &hint,
+        media_source_stream,
+        &FormatOptions::default(),
+        &MetadataOptions::default(),
+    )?;
+
+    Ok(probed.format)
+}
+
+fn audio_tracks(format: &dyn FormatReader) -> Vec<&Track> {
+    format
+        .tracks()
+        .iter()
+        .filter(|track| track.codec_params.codec != CODEC_TYPE_NULL)
+        .collect()
+}
+
+/// Lists the language of every audio track, in order, `None` for the
+/// tracks which are not tagged.
+pub fn list_audio_streams_with_symphonia(
+    source: Box<dyn MediaSource>,
+    extension: Option<&str>,
+) -> Result<Vec<Option<String>>, Box<dyn Error>> {
+    let format = open_format(source, extension)?;
+
+    Ok(audio_tracks(format.as_ref())
+        .iter()
+        .map(|track| track.language.clone())
+        .collect())
+}
+
+/// Decodes the packets of a track one at a time, keeping only the frames of
+/// the segment when one is selected.
+struct SymphoniaChunks {
+    format: Box<dyn FormatReader>,
+    decoder: Box<dyn Decoder>,
+    track_id: u32,
+    sample_buffer: Option<SampleBuffer<i16>>,
+    frames_to_skip: u64,
+    frames_to_keep: u64,
+    /// Sample rate and channels of the last decoded packet.
+    spec: Option<SignalSpec>,
+}
+
+impl SymphoniaChunks {
+    /// Decodes the next packet holding frames to keep, `None` at the end of
+    /// the track.
+    fn next_chunk(&mut self) -> Result<Option<Vec<i16>>, Box<dyn Error>> {
+        while self.frames_to_keep > 0 {
+            let packet = match self.format.next_packet() {
+                Ok(packet) => packet,
+                Err(SymphoniaError::IoError(error))
+                    if error.kind() == std::io::ErrorKind::UnexpectedEof =>
+                {
+                    break;
+                }
+                Err(error) => return Err(error.into()),
+            };
+
+            if packet.track_id() != self.track_id {
+                continue;
+            }
+
+            let decoded = match self.decoder.decode(&packet) {
+                Ok(decoded) => decoded,
+                // Skip corrupted packets, like other decoders do
+                Err(SymphoniaError::DecodeError(_)) => continue,
+                Err(error) => return Err(error.into()),
+            };
+
+            let spec = *decoded.spec();
+            let channels = spec.channels.count();
+            self.spec = Some(spec);
+            let needed_capacity = decoded.capacity() * channels;
+            if self
+                .sample_buffer
+                .as_ref()
+                .is_some_and(|buffer| buffer.capacity() < needed_capacity)
+            {
+                self.sample_buffer = None;
+            }
+
+            let sample_buffer = self
+                .sample_buffer
+                .get_or_insert_with(|| SampleBuffer::new(decoded.capacity() as u64, spec));
+            sample_buffer.copy_interleaved_ref(decoded);
+
+            let decoded_samples = sample_buffer.samples();
+            let skipped = (self.frames_to_skip as usize * channels).min(decoded_samples.len());
+            self.frames_to_skip -= (skipped / channels) as u64;
+
+            let kept = &decoded_samples[skipped..];
+            let kept = &kept[..kept.len().min((self.frames_to_keep as usize).saturating_mul(channels))];
+            self.frames_to_keep -= (kept.len() / channels) as u64;
+
+            if !kept.is_empty() {
+                return Ok(Some(kept.to_vec()));
+            }
+        }
+
+        Ok(None)
+    }
+}
+
+/// This function decodes audio in pure Rust with Symphonia, as it is read.
+/// When a `segment` duration is requested and the track is longer than
+/// that, only the segment in the middle of the track is decoded. The first
+/// track is used unless a `stream` is selected.
+pub fn decode_with_symphonia(
+    source: Box<dyn MediaSource>,
+    extension: Option<&str>,
+    options: &DecodeOptions,
+) -> Result<DecodedAudio, Box<dyn Error>> {
+    let mut format = open_format(source, extension)?;
+
+    let tracks = audio_tracks(format.as_ref());
+    let track = match options.stream {
+        Some(stream) => {
+            let languages: Vec<Option<String>> =
+                tracks.iter().map(|track| track.language.clone()).collect();
+            let position = stream
+                .position(&languages)
+                .ok_or_else(|| FingerprintError::AudioStreamNotFound(stream.clone()))?;
+            tracks[position as usize]
+        }
+        None => tracks.first().ok_or("No audio track found")?,
+    };
+    let track_id = track.id;
+    let time_base = track.codec_params.time_base;
+
+    // Symphonia has no Opus decoder, FFMpeg is tried next
+    if track.codec_params.codec == CODEC_TYPE_OPUS {
+        return Err(Box::new(FingerprintError::UnsupportedCodec("Opus")));
+    }
+
+    let mut decoder = symphonia::default::get_codecs()
+        .make(&track.codec_params, &DecoderOptions::default())?;
+
+    // Frames to drop before the segment, and to keep, when the length of
+    // the track is known
+
+    let frames = match (track.codec_params.n_frames, track.codec_params.sample_rate) {
+        (Some(frames), _) => Some(frames),
+        (None, Some(sample_rate)) => options
+            .duration
+            .map(|duration| (duration.as_secs_f64() * sample_rate as f64) as u64),
+        (None, None) => None,
+    };
+
+    let mut frames_to_skip = 0u64;
+    let mut frames_to_keep = u64::MAX;
+
+    if let (Some(segment), Some(frames), Some(sample_rate)) =
+        (options.segment, frames, track.codec_params.sample_rate)
+    {
+        let segment_frames = (segment.as_secs_f64() * sample_rate as f64) as u64;
+
+        if frames > segment_frames {
+            let start_frame = (frames - segment_frames) / 2;
+            frames_to_keep = segment_frames;
+
+            // Timestamps are in units of the time base of the track, which
+            // are not always frames, so seek to a time instead
+            let seeked = format.seek(
+                SeekMode::Accurate,
+                SeekTo::Time {
+                    time: Time::from(start_frame as f64 / sample_rate as f64),
+                    track_id: Some(track_id),
+                },
+            );
+
+            // Otherwise decode from the start, dropping what comes before
+            // the segment
+            frames_to_skip = match seeked {
+                Ok(seeked) => {
+                    decoder.reset();
+                    let skipped_ts = seeked.required_ts.saturating_sub(seeked.actual_ts);
+                    match time_base {
+                        Some(time_base) => {
+                            let skipped = time_base.calc_time(skipped_ts);
+                            ((skipped.seconds as f64 + skipped.frac) * sample_rate as f64).round() as u64
+                        }
+                        None => skipped_ts,
+                    }
+                }
+                Err(_) => start_frame,
+            };
+        }
+    }
+
+    let mut chunks = SymphoniaChunks {
+        format,
+        decoder,
+        track_id,
+        sample_buffer: None,
+        frames_to_skip,
+        frames_to_keep,
+        spec: None,
+    };
+
+    // The first packet tells the sample rate and channels, which codecs do
+    // not always declare
+    let first_chunk = chunks.next_chunk()?.ok_or("No audio could be decoded")?;
+    let spec = chunks.spec.ok_or("No audio could be decoded")?;
+
+    let duration = frames.map(|frames| {
+        Duration::from_secs_f64(frames.min(frames_to_keep) as f64 / spec.rate as f64)
+    });
+    let remaining_chunks = std::iter::from_fn(move || chunks.next_chunk().transpose());
+
+    Ok(DecodedAudio {
+        sample_rate: spec.rate,
+        channels: spec.channels.count() as u16,
+        duration,
+        chunks: Box::new(std::iter::once(Ok(first_chunk)).chain(remaining_chunks)),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fingerprinting::ffmpeg_wrapper::FFMpegSettings;
+    use std::io::Cursor;
+
+    /// A mono 16-bit WAV file whose samples count up from 0.
+    fn counting_wav(sample_rate: u32, frames: u32) -> Vec<u8> {
+        let mut wav = b"RIFF".to_vec();
+        wav.extend_from_slice(&(36 + frames * 2).to_le_bytes());
+        wav.extend_from_slice(b"WAVEfmt ");
+        wav.extend_from_slice(&16u32.to_le_bytes());
+        wav.extend_from_slice(&1u16.to_le_bytes()); // PCM
+        wav.extend_from_slice(&1u16.to_le_bytes()); // channels
+        wav.extend_from_slice(&sample_rate.to_le_bytes());
+        wav.extend_from_slice(&(sample_rate * 2).to_le_bytes());
+        wav.extend_from_slice(&2u16.to_le_bytes());
+        wav.extend_from_slice(&16u16.to_le_bytes());
+        wav.extend_from_slice(b"data");
+        wav.extend_from_slice(&(frames * 2).to_le_bytes());
+        for frame in 0..frames {
+            wav.extend_from_slice(&((frame % 30000) as i16).to_le_bytes());
+        }
+        wav
+    }
+
+    #[test]
+    fn decodes_only_the_middle_segment() {
+        let wav = counting_wav(8000, 80000);
+        let ffmpeg = FFMpegSettings::default();
+        let options = DecodeOptions {
+            segment: Some(Duration::from_secs(2)),
+            stream: None,
+            ffmpeg: &ffmpeg,
+            duration: None,
+        };
+
+        let decoded = decode_with_symphonia(Box::new(Cursor::new(wav)), Some("wav"), &options).unwrap();
+        let samples: Vec<i16> = decoded.chunks.flat_map(Result::unwrap).collect();
+
+        // The segment starts 4 s in, at frame 32000
+        assert_eq!(samples.len(), 16000);
+        assert_eq!(samples[0], 2000);
+        assert_eq!(decoded.duration, Some(Duration::from_secs(2)));
+    }
+}
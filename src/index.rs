@@ -0,0 +1,105 @@
+use crate::errors::SignatureError;
+use crate::fingerprinting::database;
+use crate::fingerprinting::landmark_index::{LandmarkIndex, LandmarkMatch};
+use crate::fingerprinting::signature_format::DecodedSignature;
+use crate::response::{IndexMatch, Signature};
+use log::debug;
+use pyo3::{pyclass, pymethods, PyErr, PyResult};
+use std::error::Error;
+
+fn to_signature_error(e: Box<dyn Error>) -> PyErr {
+    let error_message = format!("{}", e);
+    PyErr::new::<SignatureError, _>(SignatureError::new(error_message))
+}
+
+fn decode_signature(signature: &Signature) -> PyResult<DecodedSignature> {
+    DecodedSignature::decode_from_uri(&signature.signature.uri).map_err(to_signature_error)
+}
+
+fn to_index_match(found: Option<LandmarkMatch>, min_votes: Option<u32>) -> Option<IndexMatch> {
+    let min_votes = min_votes.unwrap_or(1);
+
+    found
+        .filter(|found| found.votes >= min_votes)
+        .map(|found| IndexMatch {
+            offset_seconds: found.offset_seconds(),
+            track_id: found.track_id,
+            votes: found.votes,
+        })
+}
+
+#[pyclass]
+pub(crate) struct FingerprintIndex {
+    index: LandmarkIndex,
+}
+
+#[pymethods]
+impl FingerprintIndex {
+    #[new]
+    pub fn new() -> Self {
+        FingerprintIndex {
+            index: LandmarkIndex::new(),
+        }
+    }
+
+    fn add(&mut self, track_id: String, signature: Signature) -> PyResult<()> {
+        let decoded = decode_signature(&signature)?;
+        self.index.add_signature(&track_id, &decoded);
+        debug!(
+            "Indexed track {}, index now holds {} tracks and {} hashes",
+            track_id,
+            self.index.track_count(),
+            self.index.hash_count(),
+        );
+        Ok(())
+    }
+
+    fn query(&self, signature: Signature, min_votes: Option<u32>) -> PyResult<Option<IndexMatch>> {
+        let decoded = decode_signature(&signature)?;
+        Ok(to_index_match(self.index.query(&decoded), min_votes))
+    }
+
+    fn __len__(&self) -> usize {
+        self.index.track_count()
+    }
+}
+
+#[pyclass]
+pub(crate) struct FingerprintDatabase {
+    database: database::FingerprintDatabase,
+}
+
+#[pymethods]
+impl FingerprintDatabase {
+    #[new]
+    pub fn new(path: String) -> PyResult<Self> {
+        let database = database::FingerprintDatabase::open(&path).map_err(to_signature_error)?;
+        debug!(
+            "Opened fingerprint database {} with {} tracks",
+            path,
+            database.track_count(),
+        );
+        Ok(FingerprintDatabase { database })
+    }
+
+    fn add(&mut self, track_id: String, signature: Signature) -> PyResult<()> {
+        let decoded = decode_signature(&signature)?;
+        self.database
+            .add_signature(&track_id, &decoded)
+            .map_err(to_signature_error)
+    }
+
+    fn query(&self, signature: Signature, min_votes: Option<u32>) -> PyResult<Option<IndexMatch>> {
+        let decoded = decode_signature(&signature)?;
+        let found = self.database.query(&decoded).map_err(to_signature_error)?;
+        Ok(to_index_match(found, min_votes))
+    }
+
+    fn compact(&mut self) -> PyResult<()> {
+        self.database.compact().map_err(to_signature_error)
+    }
+
+    fn __len__(&self) -> usize {
+        self.database.track_count()
+    }
+}
@@ -1,19 +1,65 @@
+// pyo3 0.20 expands `#[pymethods]` into impls nested in anonymous consts
+#![allow(non_local_definitions)]
+
+#[cfg(feature = "client")]
+mod client;
 mod errors;
 mod fingerprinting;
+mod index;
+mod match_response;
 mod response;
 mod utils;
 mod params;
 
-use crate::errors::SignatureError;
-use crate::response::{Geolocation, Signature, SignatureSong};
+// Rust API to plug in custom decoders, used by the recognizers created
+// afterwards
+pub use fingerprinting::audio_format::{sniff_format, AudioFormat};
+pub use fingerprinting::audio_stream::StreamSelector;
+#[cfg(feature = "symphonia")]
+pub use fingerprinting::decoder::SymphoniaDecoder;
+pub use fingerprinting::decoder::{
+    AudioDecoder, AudioInput, DecodeOptions, DecodedAudio, DecoderRegistry, FFMpegDecoder,
+    PcmChunks, RodioDecoder,
+};
+pub use fingerprinting::errors::FingerprintError;
+pub use fingerprinting::ffmpeg_wrapper::FFMpegSettings;
+pub use fingerprinting::shared_bytes::SharedBytes;
+
+use crate::errors::{
+    FFmpegError, FFmpegNotFoundError, FFmpegOutputTooLargeError, FFmpegTimeoutError,
+    FFprobeNotFoundError, SignatureError, UnsupportedCodecError,
+};
+use crate::index::{FingerprintDatabase, FingerprintIndex};
+use crate::match_response::{Hub, HubAction, MatchResponse, Section, Track, TrackImages, TrackMatch};
+use crate::response::{
+    Alignment, DuplicateGroup, DuplicateReport, Geolocation, IndexMatch, MediaInfo, QualityReport, Signature,
+    SignatureSong, StreamSignature,
+};
 use crate::params::SearchParams;
 use crate::utils::convert_signature_to_py;
+use crate::utils::{convert_geolocation_from_py, convert_geolocation_to_py};
+use crate::utils::convert_error_to_py;
 use crate::utils::get_python_future;
+use crate::utils::BytesLike;
+use crate::utils::read_pcm_buffer;
 use crate::utils::unwrap_decoded_signature;
 use fingerprinting::algorithm::SignatureGenerator;
+use fingerprinting::alignment::align_signatures;
+use fingerprinting::communication::{system_timezone, Clock, RequestContext, DEFAULT_TIMEZONE};
+use fingerprinting::duplicates::find_duplicate_groups;
+use fingerprinting::media_info::{probe_bytes, probe_file};
+use fingerprinting::pcm::SampleFormat;
+use fingerprinting::ffmpeg_wrapper::{
+    is_working_ffmpeg, DEFAULT_MAX_OUTPUT_BYTES, DEFAULT_TIMEOUT,
+};
 use pyo3::prelude::*;
 use pyo3::{pyclass, pymethods, pymodule, PyErr, PyObject, PyResult, Python, ToPyObject};
-use log::{info, debug, error};
+use log::{info, debug, error, warn};
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::atomic::AtomicBool;
+use std::sync::Arc;
+use std::time::Duration;
 
 #[pymodule]
 fn shazamio_core(_py: Python<'_>, m: &PyModule) -> PyResult<()> {
@@ -22,68 +68,225 @@ fn shazamio_core(_py: Python<'_>, m: &PyModule) -> PyResult<()> {
 
     m.add_class::<Recognizer>()?;
     m.add_class::<SignatureError>()?;
+    m.add_class::<FFmpegNotFoundError>()?;
+    m.add_class::<FFprobeNotFoundError>()?;
+    m.add_class::<UnsupportedCodecError>()?;
+    m.add_class::<FFmpegTimeoutError>()?;
+    m.add_class::<FFmpegOutputTooLargeError>()?;
+    m.add_class::<FFmpegError>()?;
     m.add_class::<Geolocation>()?;
     m.add_class::<SignatureSong>()?;
     m.add_class::<Signature>()?;
+    m.add_class::<QualityReport>()?;
     m.add_class::<SearchParams>()?;
+    m.add_class::<FingerprintIndex>()?;
+    m.add_class::<FingerprintDatabase>()?;
+    m.add_class::<IndexMatch>()?;
+    m.add_class::<DuplicateGroup>()?;
+    m.add_class::<DuplicateReport>()?;
+    m.add_class::<Alignment>()?;
+    m.add_class::<MediaInfo>()?;
+    m.add_class::<StreamSignature>()?;
+    m.add_class::<MatchResponse>()?;
+    m.add_class::<TrackMatch>()?;
+    m.add_class::<Track>()?;
+    m.add_class::<TrackImages>()?;
+    m.add_class::<Hub>()?;
+    m.add_class::<HubAction>()?;
+    m.add_class::<Section>()?;
+    #[cfg(feature = "client")]
+    {
+        m.add_class::<client::RecognitionClient>()?;
+        m.add_class::<errors::RecognitionError>()?;
+    }
 
     info!("shazamio_core module initialized successfully");
     Ok(())
 }
 
+/// A media file given by its path or its content.
+#[derive(FromPyObject)]
+enum MediaInput {
+    Bytes(BytesLike),
+    Path(PathBuf),
+}
+
 #[derive(Clone)]
 #[pyclass]
 struct Recognizer {
     #[pyo3(get, set)]
     segment_duration_seconds: u32,
+    ffmpeg: FFMpegSettings,
+    decoders: DecoderRegistry,
+    request: RequestContext,
 }
 
 #[pymethods]
 impl Recognizer {
     #[new]
-    pub fn new(segment_duration_seconds: Option<u32>) -> Self {
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        segment_duration_seconds: Option<u32>,
+        ffmpeg_path: Option<PathBuf>,
+        ffprobe_path: Option<PathBuf>,
+        require_ffmpeg: Option<bool>,
+        ffmpeg_timeout_seconds: Option<f64>,
+        ffmpeg_inactivity_timeout_seconds: Option<f64>,
+        ffmpeg_max_output_bytes: Option<u64>,
+        ffmpeg_temporary_files: Option<bool>,
+        decoder_order: Option<Vec<String>>,
+        geolocation: Option<Geolocation>,
+        timezone: Option<String>,
+        use_system_timezone: Option<bool>,
+        timestamp_ms: Option<u64>,
+    ) -> PyResult<Self> {
         let duration = segment_duration_seconds.unwrap_or(10);
         info!("Recognizer created with segment_duration_seconds = {}", duration);
-        Recognizer { segment_duration_seconds: duration }
+
+        // Zero disables a limit
+        let ffmpeg = FFMpegSettings {
+            path: ffmpeg_path.map(|path| path.to_string_lossy().into_owned()),
+            ffprobe_path: ffprobe_path.map(|path| path.to_string_lossy().into_owned()),
+            timeout: match ffmpeg_timeout_seconds {
+                Some(seconds) if seconds > 0.0 => Some(Duration::from_secs_f64(seconds)),
+                Some(_) => None,
+                None => Some(DEFAULT_TIMEOUT),
+            },
+            inactivity_timeout: ffmpeg_inactivity_timeout_seconds
+                .filter(|seconds| *seconds > 0.0)
+                .map(Duration::from_secs_f64),
+            max_output_bytes: match ffmpeg_max_output_bytes {
+                Some(0) => None,
+                Some(bytes) => Some(bytes),
+                None => Some(DEFAULT_MAX_OUTPUT_BYTES),
+            },
+            cancelled: None,
+            temporary_files: ffmpeg_temporary_files.unwrap_or(false),
+        };
+
+        if require_ffmpeg.unwrap_or(false) {
+            match ffmpeg.resolve_path() {
+                Some(path) if is_working_ffmpeg(&path) => debug!("Using FFMpeg at {}", path),
+                Some(path) => {
+                    return Err(PyErr::new::<FFmpegNotFoundError, _>(format!(
+                        "FFMpeg could not be executed from {}",
+                        path
+                    )))
+                }
+                None => {
+                    return Err(PyErr::new::<FFmpegNotFoundError, _>(
+                        "FFMpeg was not found, install it or set SHAZAMIO_FFMPEG".to_string(),
+                    ))
+                }
+            }
+        }
+
+        let mut decoders = DecoderRegistry::global();
+        if let Some(decoder_order) = decoder_order {
+            let names: Vec<&str> = decoder_order.iter().map(String::as_str).collect();
+            decoders
+                .set_order(&names)
+                .map_err(|e| PyErr::new::<SignatureError, _>(e.to_string()))?;
+        }
+        debug!("Decoders tried in order: {:?}", decoders.names());
+
+        // An explicit timezone wins over the one of the system
+        let timezone = match (timezone, use_system_timezone.unwrap_or(false)) {
+            (Some(timezone), _) => timezone,
+            (None, true) => system_timezone().unwrap_or_else(|| {
+                warn!("The timezone of the system is unknown, using {}", DEFAULT_TIMEZONE);
+                DEFAULT_TIMEZONE.to_string()
+            }),
+            (None, false) => DEFAULT_TIMEZONE.to_string(),
+        };
+        let request = RequestContext {
+            geolocation: geolocation
+                .as_ref()
+                .map(convert_geolocation_from_py)
+                .unwrap_or_default(),
+            timezone,
+            clock: timestamp_ms.map_or(Clock::System, Clock::Fixed),
+        };
+        debug!("Requests are sent with {:?}", request);
+
+        Ok(Recognizer {
+            segment_duration_seconds: duration,
+            ffmpeg,
+            decoders,
+            request,
+        })
+    }
+
+    /// Geolocation sent with the signatures, unless overridden by the
+    /// search parameters.
+    #[getter]
+    fn geolocation(&self) -> PyResult<Geolocation> {
+        convert_geolocation_to_py(&self.request.geolocation)
+    }
+
+    /// Timezone sent with the signatures, unless overridden by the search
+    /// parameters.
+    #[getter]
+    fn timezone(&self) -> String {
+        self.request.timezone.clone()
+    }
+
+    /// Names of the decoders, in the order they are tried.
+    #[getter]
+    fn decoders(&self) -> Vec<String> {
+        self.decoders.names().into_iter().map(String::from).collect()
+    }
+
+    /// Path of FFMpeg used as a decoding fallback, if any.
+    #[getter]
+    fn ffmpeg_path(&self) -> Option<String> {
+        self.ffmpeg.resolve_path()
+    }
+
+    /// Path of FFProbe used to select audio streams by language, if any.
+    #[getter]
+    fn ffprobe_path(&self) -> Option<String> {
+        self.ffmpeg.resolve_ffprobe_path()
     }
 
     fn recognize_bytes(
         &self,
         py: Python,
-        value: Vec<u8>,
+        value: BytesLike,
         options: Option<SearchParams>,
     ) -> PyResult<PyObject> {
         debug!(
             "recognize_bytes method called with bytes len: {} and options: {:?}",
-            value.len(),
+            value.0.len(),
             options,
         );
 
-        let search_options = options.unwrap_or_else(|| {
-            debug!(
-                "Options not provided, using default segment duration {}",
-                self.segment_duration_seconds,
-            );
-            SearchParams::new(Option::from(self.segment_duration_seconds))
-        });
+        let search_options = self.search_options(options)?;
+
+        let cancelled = Arc::new(AtomicBool::new(false));
+        let ffmpeg = self.ffmpeg.cancelled_by(cancelled.clone());
+        let decoders = self.decoders.clone();
+        let request = self.request_context(&search_options);
 
         let future = async move {
             debug!("Starting async recognition from bytes");
             let data = SignatureGenerator::make_signature_from_bytes(
-                value,
-                Some(search_options.segment_duration_seconds),
+                value.0,
+                &search_options,
+                &decoders,
+                &ffmpeg,
             ).map_err(|e| {
                 error!("Error in make_signature_from_bytes: {}", e);
                 let error_message = format!("{}", e);
-                PyErr::new::<SignatureError, _>(SignatureError::new(error_message))
+                convert_error_to_py(&*e, error_message)
             })?;
 
             debug!("Successfully generated signature from bytes");
-            let signature = unwrap_decoded_signature(data);
+            let signature = unwrap_decoded_signature(data, &request);
             convert_signature_to_py(signature?)
         };
 
-        let python_future = get_python_future(py, future);
+        let python_future = get_python_future(py, cancelled, future);
         debug!("Returning Python future for recognize_bytes");
         python_future.map(|any| any.to_object(py))
     }
@@ -100,32 +303,323 @@ impl Recognizer {
             options,
         );
 
-        let search_options = options.unwrap_or_else(|| {
-            debug!(
-                "Options not provided, using default segment duration {}",
-                self.segment_duration_seconds,
-            );
-            SearchParams::new(Option::from(self.segment_duration_seconds))
-        });
+        let search_options = self.search_options(options)?;
+
+        let cancelled = Arc::new(AtomicBool::new(false));
+        let ffmpeg = self.ffmpeg.cancelled_by(cancelled.clone());
+        let decoders = self.decoders.clone();
+        let request = self.request_context(&search_options);
 
         let future = async move {
             debug!("Starting async recognition from file: {}", value);
             let data = SignatureGenerator::make_signature_from_file(
                 &value,
-                Some(search_options.segment_duration_seconds),
+                &search_options,
+                &decoders,
+                &ffmpeg,
             ).map_err(|e| {
                 debug!("Error in make_signature_from_file: {}", e);
                 let error_message = format!("{}", e);
-                PyErr::new::<SignatureError, _>(SignatureError::new(error_message))
+                convert_error_to_py(&*e, error_message)
             })?;
 
             debug!("Successfully generated signature from file");
-            let signature = unwrap_decoded_signature(data);
+            let signature = unwrap_decoded_signature(data, &request);
             convert_signature_to_py(signature?)
         };
 
-        let python_future = get_python_future(py, future);
+        let python_future = get_python_future(py, cancelled, future);
         debug!("Returning Python future for recognize_path");
         python_future.map(|any| any.to_object(py))
     }
+
+    fn recognize_pcm(
+        &self,
+        py: Python,
+        samples: &PyAny,
+        sample_rate: u32,
+        channels: Option<u16>,
+        sample_format: Option<&str>,
+        options: Option<SearchParams>,
+    ) -> PyResult<PyObject> {
+        let channels = channels.unwrap_or(1);
+        let sample_format = SampleFormat::parse(sample_format.unwrap_or("i16"))
+            .map_err(|e| PyErr::new::<SignatureError, _>(e.to_string()))?;
+
+        debug!(
+            "recognize_pcm method called with {:?} samples at {} Hz, {} channels and options: {:?}",
+            sample_format,
+            sample_rate,
+            channels,
+            options,
+        );
+
+        // The buffer can only be read while holding the GIL
+        let samples = read_pcm_buffer(py, samples, sample_format)?;
+
+        let search_options = self.search_options(options)?;
+
+        let cancelled = Arc::new(AtomicBool::new(false));
+        let request = self.request_context(&search_options);
+
+        let future = async move {
+            debug!("Starting async recognition from {} PCM samples", samples.len());
+            let data = SignatureGenerator::make_signature_from_interleaved_pcm(
+                samples,
+                sample_rate,
+                channels,
+                &search_options,
+            ).map_err(|e| {
+                debug!("Error in make_signature_from_interleaved_pcm: {}", e);
+                let error_message = format!("{}", e);
+                convert_error_to_py(&*e, error_message)
+            })?;
+
+            debug!("Successfully generated signature from PCM samples");
+            let signature = unwrap_decoded_signature(data, &request);
+            convert_signature_to_py(signature?)
+        };
+
+        let python_future = get_python_future(py, cancelled, future);
+        debug!("Returning Python future for recognize_pcm");
+        python_future.map(|any| any.to_object(py))
+    }
+
+    fn recognize_streams(
+        &self,
+        py: Python,
+        value: MediaInput,
+        options: Option<SearchParams>,
+    ) -> PyResult<PyObject> {
+        let search_options = self.search_options(options)?;
+
+        let cancelled = Arc::new(AtomicBool::new(false));
+        let ffmpeg = self.ffmpeg.cancelled_by(cancelled.clone());
+        let decoders = self.decoders.clone();
+        let request = self.request_context(&search_options);
+
+        let future = async move {
+            let signatures = match value {
+                MediaInput::Bytes(BytesLike(bytes)) => {
+                    debug!("recognize_streams method called with bytes len: {}", bytes.len());
+                    SignatureGenerator::make_signatures_of_all_streams_from_bytes(bytes, &search_options, &decoders, &ffmpeg)
+                }
+                MediaInput::Path(path) => {
+                    debug!("recognize_streams method called with path: {}", path.display());
+                    SignatureGenerator::make_signatures_of_all_streams_from_file(
+                        &path.to_string_lossy(),
+                        &search_options,
+                        &decoders,
+                        &ffmpeg,
+                    )
+                }
+            }
+            .map_err(|e| {
+                debug!("Error in make_signatures_of_all_streams: {}", e);
+                let error_message = format!("{}", e);
+                convert_error_to_py(&*e, error_message)
+            })?;
+
+            debug!("Successfully generated {} stream signatures", signatures.len());
+            Ok(signatures
+                .into_iter()
+                .enumerate()
+                .map(|(stream_index, (language, data))| {
+                    let signature = data
+                        .map_err(|e| e.to_string())
+                        .and_then(|data| {
+                            unwrap_decoded_signature(data, &request)
+                                .and_then(convert_signature_to_py)
+                                .map_err(|e| e.to_string())
+                        });
+                    StreamSignature {
+                        stream_index: stream_index as u32,
+                        language,
+                        error: signature.as_ref().err().cloned(),
+                        signature: signature.ok(),
+                    }
+                })
+                .collect::<Vec<_>>())
+        };
+
+        let python_future = get_python_future(py, cancelled, future);
+        debug!("Returning Python future for recognize_streams");
+        python_future.map(|any| any.to_object(py))
+    }
+
+    fn find_duplicates(
+        &self,
+        py: Python,
+        paths: Vec<String>,
+        min_similarity: Option<f32>,
+    ) -> PyResult<PyObject> {
+        debug!(
+            "find_duplicates method called with {} paths and min_similarity: {:?}",
+            paths.len(),
+            min_similarity,
+        );
+
+        let min_similarity = min_similarity.unwrap_or(0.1);
+        let cancelled = Arc::new(AtomicBool::new(false));
+        let ffmpeg = self.ffmpeg.cancelled_by(cancelled.clone());
+        let decoders = self.decoders.clone();
+
+        let future = async move {
+            let mut fingerprinted_paths = vec![];
+            let mut signatures = vec![];
+            let mut failed = HashMap::new();
+
+            for path in paths {
+                match SignatureGenerator::make_full_length_signature_from_file(&path, false, &decoders, &ffmpeg) {
+                    Ok(signature) => {
+                        fingerprinted_paths.push(path);
+                        signatures.push(signature);
+                    }
+                    Err(e) => {
+                        warn!("Skipping {} in duplicate detection: {}", path, e);
+                        failed.insert(path, e.to_string());
+                    }
+                }
+            }
+
+            let groups = find_duplicate_groups(&signatures, min_similarity);
+            debug!("Found {} duplicate groups", groups.len());
+
+            let groups = groups
+                .into_iter()
+                .map(|group| DuplicateGroup {
+                    paths: group
+                        .members
+                        .iter()
+                        .map(|member| fingerprinted_paths[*member].clone())
+                        .collect(),
+                    similarity: group.similarity,
+                })
+                .collect();
+            Ok(DuplicateReport { groups, failed })
+        };
+
+        let python_future = get_python_future(py, cancelled, future);
+        debug!("Returning Python future for find_duplicates");
+        python_future.map(|any| any.to_object(py))
+    }
+
+    fn align(
+        &self,
+        py: Python,
+        reference: String,
+        other: String,
+        estimate_drift: Option<bool>,
+    ) -> PyResult<PyObject> {
+        debug!(
+            "align method called with reference: {}, other: {} and estimate_drift: {:?}",
+            reference,
+            other,
+            estimate_drift,
+        );
+
+        let estimate_drift = estimate_drift.unwrap_or(false);
+        let cancelled = Arc::new(AtomicBool::new(false));
+        let ffmpeg = self.ffmpeg.cancelled_by(cancelled.clone());
+        let decoders = self.decoders.clone();
+
+        let future = async move {
+            let fingerprint = |path: &str| {
+                SignatureGenerator::make_full_length_signature_from_file(path, false, &decoders, &ffmpeg).map_err(|e| {
+                    error!("Error in make_full_length_signature_from_file: {}", e);
+                    let error_message = format!("{}: {}", path, e);
+                    convert_error_to_py(&*e, error_message)
+                })
+            };
+
+            let reference_signature = fingerprint(&reference)?;
+            let other_signature = fingerprint(&other)?;
+
+            let alignment =
+                align_signatures(&reference_signature, &other_signature, estimate_drift);
+            debug!("Alignment result: {:?}", alignment);
+
+            Ok(alignment.map(|alignment| Alignment {
+                offset_ms: alignment.offset_seconds * 1000.0,
+                confidence: alignment.confidence,
+                votes: alignment.votes,
+                drift_ppm: alignment.drift_ppm,
+            }))
+        };
+
+        let python_future = get_python_future(py, cancelled, future);
+        debug!("Returning Python future for align");
+        python_future.map(|any| any.to_object(py))
+    }
+
+    fn probe(&self, py: Python, value: MediaInput) -> PyResult<PyObject> {
+        let cancelled = Arc::new(AtomicBool::new(false));
+        let ffmpeg = self.ffmpeg.cancelled_by(cancelled.clone());
+
+        let future = async move {
+            let info = match value {
+                MediaInput::Bytes(BytesLike(bytes)) => {
+                    debug!("probe method called with bytes len: {}", bytes.len());
+                    probe_bytes(bytes, &ffmpeg)
+                }
+                MediaInput::Path(path) => {
+                    debug!("probe method called with path: {}", path.display());
+                    probe_file(&path.to_string_lossy(), &ffmpeg)
+                }
+            }
+            .map_err(|e| {
+                debug!("Error in probe: {}", e);
+                let error_message = format!("{}", e);
+                convert_error_to_py(&*e, error_message)
+            })?;
+
+            Ok(MediaInfo {
+                duration_seconds: info.duration_seconds,
+                container: info.container,
+                codec: info.codec,
+                sample_rate: info.sample_rate,
+                channels: info.channels,
+                bitrate: info.bitrate,
+                tags: info.tags,
+            })
+        };
+
+        let python_future = get_python_future(py, cancelled, future);
+        debug!("Returning Python future for probe");
+        python_future.map(|any| any.to_object(py))
+    }
+}
+
+impl Recognizer {
+    /// The given search parameters, or the defaults with the segment
+    /// duration of the recognizer. Raises `ValueError` when the given
+    /// parameters contradict each other.
+    fn search_options(&self, options: Option<SearchParams>) -> PyResult<SearchParams> {
+        let options = options.unwrap_or_else(|| {
+            debug!(
+                "Options not provided, using default segment duration {}",
+                self.segment_duration_seconds,
+            );
+            SearchParams::with_segment(self.segment_duration_seconds)
+        });
+        options.validate()?;
+        Ok(options)
+    }
+
+    /// Geolocation and timezone to send, the search parameters overriding
+    /// those of the recognizer.
+    fn request_context(&self, options: &SearchParams) -> RequestContext {
+        RequestContext {
+            geolocation: options
+                .geolocation
+                .as_ref()
+                .map(convert_geolocation_from_py)
+                .unwrap_or_else(|| self.request.geolocation.clone()),
+            timezone: options
+                .timezone
+                .clone()
+                .unwrap_or_else(|| self.request.timezone.clone()),
+            clock: options.timestamp_ms.map_or(self.request.clock, Clock::Fixed),
+        }
+    }
 }
@@ -0,0 +1,304 @@
+use crate::utils::convert_json_object_to_py;
+use pyo3::exceptions::PyValueError;
+use pyo3::{pyclass, pymethods, PyObject, PyResult, Python};
+use serde::{Deserialize, Serialize};
+
+/// Fields of the answer which are not modelled, kept as they were received.
+type ExtraFields = serde_json::Map<String, serde_json::Value>;
+
+/// Answer of the discovery endpoint to a recognition request.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[pyclass]
+pub(crate) struct MatchResponse {
+    #[pyo3(get)]
+    #[serde(default)]
+    pub(crate) matches: Vec<TrackMatch>,
+    /// Best matching track, missing when nothing matched.
+    #[pyo3(get)]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) track: Option<Track>,
+    #[pyo3(get)]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) tagid: Option<String>,
+    #[pyo3(get)]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) timestamp: Option<u64>,
+    #[pyo3(get)]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) timezone: Option<String>,
+    /// Milliseconds to wait before recognizing more audio of the same
+    /// recording, sent when nothing matched.
+    #[pyo3(get)]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) retryms: Option<u64>,
+    #[serde(flatten)]
+    pub(crate) extra: ExtraFields,
+}
+
+/// Position of the signature within a matching recording.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[pyclass]
+pub(crate) struct TrackMatch {
+    #[pyo3(get)]
+    pub(crate) id: String,
+    /// Seconds from the start of the recording.
+    #[pyo3(get)]
+    pub(crate) offset: f64,
+    #[pyo3(get)]
+    #[serde(default)]
+    pub(crate) timeskew: f64,
+    #[pyo3(get)]
+    #[serde(default)]
+    pub(crate) frequencyskew: f64,
+    #[serde(flatten)]
+    pub(crate) extra: ExtraFields,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[pyclass]
+pub(crate) struct Track {
+    #[pyo3(get)]
+    pub(crate) key: String,
+    #[pyo3(get)]
+    pub(crate) title: String,
+    /// Usually the artist.
+    #[pyo3(get)]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) subtitle: Option<String>,
+    #[pyo3(get)]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) isrc: Option<String>,
+    #[pyo3(get)]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) url: Option<String>,
+    #[pyo3(get)]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) images: Option<TrackImages>,
+    #[pyo3(get)]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) hub: Option<Hub>,
+    #[pyo3(get)]
+    #[serde(default)]
+    pub(crate) sections: Vec<Section>,
+    #[serde(flatten)]
+    pub(crate) extra: ExtraFields,
+}
+
+/// URLs of the artwork of a track.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[pyclass]
+pub(crate) struct TrackImages {
+    #[pyo3(get)]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) background: Option<String>,
+    #[pyo3(get)]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) coverart: Option<String>,
+    #[pyo3(get)]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) coverarthq: Option<String>,
+    #[serde(flatten)]
+    pub(crate) extra: ExtraFields,
+}
+
+/// Where a track can be played, such as a streaming service.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[pyclass]
+pub(crate) struct Hub {
+    #[pyo3(get)]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) r#type: Option<String>,
+    #[pyo3(get)]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) displayname: Option<String>,
+    #[pyo3(get)]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) image: Option<String>,
+    #[pyo3(get)]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) explicit: Option<bool>,
+    #[pyo3(get)]
+    #[serde(default)]
+    pub(crate) actions: Vec<HubAction>,
+    #[serde(flatten)]
+    pub(crate) extra: ExtraFields,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[pyclass]
+pub(crate) struct HubAction {
+    #[pyo3(get)]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) name: Option<String>,
+    #[pyo3(get)]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) r#type: Option<String>,
+    #[pyo3(get)]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) id: Option<String>,
+    #[pyo3(get)]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) uri: Option<String>,
+    #[serde(flatten)]
+    pub(crate) extra: ExtraFields,
+}
+
+/// A tab of the track page, such as "SONG", "LYRICS" or "ARTIST".
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[pyclass]
+pub(crate) struct Section {
+    #[pyo3(get)]
+    pub(crate) r#type: String,
+    #[pyo3(get)]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) tabname: Option<String>,
+    #[serde(flatten)]
+    pub(crate) extra: ExtraFields,
+}
+
+#[pymethods]
+impl MatchResponse {
+    /// Parses the JSON answer of the discovery endpoint.
+    #[staticmethod]
+    pub fn from_json(json: &str) -> PyResult<Self> {
+        serde_json::from_str(json).map_err(|e| PyValueError::new_err(e.to_string()))
+    }
+
+    /// Serialises the answer back, with the fields which are not modelled.
+    pub fn to_json(&self) -> PyResult<String> {
+        serde_json::to_string(self).map_err(|e| PyValueError::new_err(e.to_string()))
+    }
+
+    #[getter]
+    pub fn extra(&self, py: Python) -> PyObject {
+        convert_json_object_to_py(py, &self.extra)
+    }
+}
+
+#[pymethods]
+impl TrackMatch {
+    #[getter]
+    pub fn extra(&self, py: Python) -> PyObject {
+        convert_json_object_to_py(py, &self.extra)
+    }
+}
+
+#[pymethods]
+impl Track {
+    #[getter]
+    pub fn extra(&self, py: Python) -> PyObject {
+        convert_json_object_to_py(py, &self.extra)
+    }
+}
+
+#[pymethods]
+impl TrackImages {
+    #[getter]
+    pub fn extra(&self, py: Python) -> PyObject {
+        convert_json_object_to_py(py, &self.extra)
+    }
+}
+
+#[pymethods]
+impl Hub {
+    #[getter]
+    pub fn extra(&self, py: Python) -> PyObject {
+        convert_json_object_to_py(py, &self.extra)
+    }
+}
+
+#[pymethods]
+impl HubAction {
+    #[getter]
+    pub fn extra(&self, py: Python) -> PyObject {
+        convert_json_object_to_py(py, &self.extra)
+    }
+}
+
+#[pymethods]
+impl Section {
+    #[getter]
+    pub fn extra(&self, py: Python) -> PyObject {
+        convert_json_object_to_py(py, &self.extra)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::{json, Value};
+
+    const MATCH: &str = include_str!(concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/match.json"));
+    const NO_MATCH: &str = include_str!(concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/no_match.json"));
+    const UNKNOWN_FIELDS: &str =
+        include_str!(concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/unknown_fields.json"));
+
+    /// Parses and serialises the answer as `from_json` and `to_json` do,
+    /// which cannot be called without Python when they fail.
+    fn assert_round_trips(json: &str) -> MatchResponse {
+        let response: MatchResponse = serde_json::from_str(json).unwrap();
+        let serialised: Value = serde_json::from_str(&serde_json::to_string(&response).unwrap()).unwrap();
+        assert_eq!(serialised, serde_json::from_str::<Value>(json).unwrap());
+        response
+    }
+
+    #[test]
+    fn parses_a_match() {
+        let response = assert_round_trips(MATCH);
+
+        assert_eq!(response.matches.len(), 1);
+        assert_eq!(response.matches[0].id, "583207718");
+        assert_eq!(response.matches[0].offset, 41.3275390625);
+        assert_eq!(response.retryms, None);
+
+        let track = response.track.unwrap();
+        assert_eq!(track.title, "Rolling in the Deep");
+        assert_eq!(track.subtitle.as_deref(), Some("Adele"));
+        assert_eq!(track.isrc.as_deref(), Some("GBBKS1000335"));
+        assert!(track.images.unwrap().coverart.is_some());
+
+        let hub = track.hub.unwrap();
+        assert_eq!(hub.r#type.as_deref(), Some("APPLEMUSIC"));
+        assert_eq!(hub.explicit, Some(false));
+        assert_eq!(hub.actions[1].r#type.as_deref(), Some("uri"));
+
+        let section_types: Vec<&str> = track.sections.iter().map(|section| section.r#type.as_str()).collect();
+        assert_eq!(section_types, ["SONG", "LYRICS"]);
+        assert!(track.extra.is_empty());
+        assert!(response.extra.is_empty());
+    }
+
+    #[test]
+    fn parses_a_missing_match() {
+        let response = assert_round_trips(NO_MATCH);
+
+        assert!(response.matches.is_empty());
+        assert!(response.track.is_none());
+        assert_eq!(response.retryms, Some(12000));
+        assert!(response.extra.is_empty());
+    }
+
+    #[test]
+    fn keeps_unknown_fields() {
+        let response = assert_round_trips(UNKNOWN_FIELDS);
+
+        assert_eq!(response.extra["location"], json!({"accuracy": 0.01}));
+        assert_eq!(response.extra["futureField"], json!({"nested": [1, 2.5, null, true]}));
+        assert_eq!(response.matches[0].extra["channel"], "L");
+
+        let track = response.track.unwrap();
+        let mut track_extra: Vec<&str> = track.extra.keys().map(String::as_str).collect();
+        track_extra.sort_unstable();
+        assert_eq!(track_extra, ["genres", "layout", "share", "type"]);
+        assert_eq!(track.images.unwrap().extra["joecolor"], "b:000000p:f5e6d3");
+
+        let hub = track.hub.unwrap();
+        assert_eq!(hub.extra["providers"], json!([]));
+        assert_eq!(hub.extra["options"][0]["caption"], "OPEN");
+        assert_eq!(hub.actions[0].extra["beacondata"]["providerName"], "applemusic");
+
+        assert!(track.sections[0].extra.contains_key("metadata"));
+        assert_eq!(track.sections[1].tabname, None);
+        assert_eq!(track.sections[1].extra["footer"], "Writer(s): Adele Adkins, Paul Epworth");
+    }
+}
@@ -1,4 +1,7 @@
-use pyo3::{pyclass, pymethods};
+use crate::fingerprinting::audio_stream::StreamSelector;
+use crate::response::Geolocation;
+use pyo3::exceptions::PyValueError;
+use pyo3::{pyclass, pymethods, PyResult};
 use serde::{Deserialize, Serialize};
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -6,13 +9,87 @@ use serde::{Deserialize, Serialize};
 pub(crate) struct SearchParams {
     #[pyo3(get, set)]
     pub(crate) segment_duration_seconds: u32,
+    #[pyo3(get, set)]
+    pub(crate) pad_last_frame: bool,
+    #[pyo3(get, set)]
+    pub(crate) audio_stream_index: Option<u32>,
+    #[pyo3(get, set)]
+    pub(crate) audio_language: Option<String>,
+    #[pyo3(get, set)]
+    pub(crate) geolocation: Option<Geolocation>,
+    #[pyo3(get, set)]
+    pub(crate) timezone: Option<String>,
+    #[pyo3(get, set)]
+    pub(crate) timestamp_ms: Option<u64>,
 }
 #[pymethods]
 impl SearchParams {
     #[new]
-    pub fn new(segment_duration_seconds: Option<u32>) -> Self {
+    pub fn new(
+        segment_duration_seconds: Option<u32>,
+        pad_last_frame: Option<bool>,
+        audio_stream_index: Option<u32>,
+        audio_language: Option<String>,
+        geolocation: Option<Geolocation>,
+        timezone: Option<String>,
+        timestamp_ms: Option<u64>,
+    ) -> PyResult<Self> {
+        let defaults = SearchParams::default();
+        let params = SearchParams {
+            segment_duration_seconds: segment_duration_seconds.unwrap_or(defaults.segment_duration_seconds),
+            pad_last_frame: pad_last_frame.unwrap_or(defaults.pad_last_frame),
+            audio_stream_index,
+            audio_language,
+            geolocation,
+            timezone,
+            timestamp_ms,
+        };
+        params.validate()?;
+        Ok(params)
+    }
+}
+
+impl Default for SearchParams {
+    fn default() -> Self {
         SearchParams {
-            segment_duration_seconds: segment_duration_seconds.unwrap_or(10),
+            segment_duration_seconds: 10,
+            pad_last_frame: false,
+            audio_stream_index: None,
+            audio_language: None,
+            geolocation: None,
+            timezone: None,
+            timestamp_ms: None,
+        }
+    }
+}
+
+impl SearchParams {
+    /// Default parameters, fingerprinting segments of the given duration.
+    pub(crate) fn with_segment(segment_duration_seconds: u32) -> Self {
+        SearchParams {
+            segment_duration_seconds,
+            ..SearchParams::default()
+        }
+    }
+
+    /// Rejects parameters selecting the audio stream both by index and by
+    /// language. Checked again when recognizing, as both can be set later.
+    pub(crate) fn validate(&self) -> PyResult<()> {
+        if self.audio_stream_index.is_some() && self.audio_language.is_some() {
+            return Err(PyValueError::new_err(
+                "audio_stream_index and audio_language cannot both be set",
+            ));
+        }
+        Ok(())
+    }
+
+    /// The audio stream to decode. Only one way of selecting it is set,
+    /// see `validate`.
+    pub(crate) fn stream_selector(&self) -> Option<StreamSelector> {
+        match (&self.audio_language, self.audio_stream_index) {
+            (Some(language), _) => Some(StreamSelector::Language(language.clone())),
+            (None, Some(index)) => Some(StreamSelector::Index(index)),
+            (None, None) => None,
         }
     }
 }
\ No newline at end of file
@@ -1,15 +1,21 @@
+use crate::fingerprinting::communication::{
+    is_valid_request_id, random_uuid, RecognitionRequest, RequestLocale,
+};
+use crate::utils::convert_signature_from_py;
+use pyo3::exceptions::PyValueError;
 use pyo3::{pyclass, pymethods, PyResult};
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 
-#[derive(Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 #[pyclass]
 pub(crate) struct Geolocation {
     #[pyo3(get)]
-    pub(crate) altitude: i16,
+    pub(crate) altitude: f64,
     #[pyo3(get)]
-    pub(crate) latitude: i8,
+    pub(crate) latitude: f64,
     #[pyo3(get)]
-    pub(crate) longitude: i8,
+    pub(crate) longitude: f64,
 }
 
 #[derive(Clone, Serialize, Deserialize)]
@@ -18,11 +24,36 @@ pub(crate) struct SignatureSong {
     #[pyo3(get)]
     pub(crate) samples: u32,
     #[pyo3(get)]
-    pub(crate) timestamp: u32,
+    pub(crate) samples_used: u32,
+    #[pyo3(get)]
+    pub(crate) timestamp: u64,
     #[pyo3(get)]
     pub(crate) uri: String,
 }
 
+#[derive(Clone, Serialize, Deserialize)]
+#[pyclass]
+pub(crate) struct QualityReport {
+    #[pyo3(get)]
+    pub(crate) duration_seconds: f32,
+    #[pyo3(get)]
+    pub(crate) peaks_per_second: HashMap<String, f32>,
+    #[pyo3(get)]
+    pub(crate) rms_dbfs: f32,
+    #[pyo3(get)]
+    pub(crate) peak_dbfs: f32,
+    #[pyo3(get)]
+    pub(crate) clipped_ratio: f32,
+    #[pyo3(get)]
+    pub(crate) silence_ratio: f32,
+    #[pyo3(get)]
+    pub(crate) effective_bandwidth_hz: f32,
+    #[pyo3(get)]
+    pub(crate) likely_unrecognizable: bool,
+    #[pyo3(get)]
+    pub(crate) reasons: Vec<String>,
+}
+
 #[derive(Clone, Serialize, Deserialize)]
 #[pyclass]
 pub(crate) struct Signature {
@@ -31,15 +62,33 @@ pub(crate) struct Signature {
     #[pyo3(get)]
     pub(crate) signature: SignatureSong,
     #[pyo3(get)]
-    pub(crate) timestamp: u32,
+    pub(crate) timestamp: u64,
     #[pyo3(get)]
     pub(crate) timezone: String,
+    #[pyo3(get)]
+    pub(crate) quality: Option<QualityReport>,
 }
 
 #[pymethods]
 impl Geolocation {
     #[new]
-    pub fn new(altitude: i16, latitude: i8, longitude: i8) -> PyResult<Self> {
+    pub fn new(altitude: f64, latitude: f64, longitude: f64) -> PyResult<Self> {
+        if !(-90.0..=90.0).contains(&latitude) {
+            return Err(PyValueError::new_err(format!(
+                "latitude must be between -90 and 90 degrees, got {}",
+                latitude
+            )));
+        }
+        if !(-180.0..=180.0).contains(&longitude) {
+            return Err(PyValueError::new_err(format!(
+                "longitude must be between -180 and 180 degrees, got {}",
+                longitude
+            )));
+        }
+        if !altitude.is_finite() {
+            return Err(PyValueError::new_err(format!("altitude must be finite, got {}", altitude)));
+        }
+
         Ok(Geolocation {
             altitude,
             latitude,
@@ -51,9 +100,13 @@ impl Geolocation {
 #[pymethods]
 impl SignatureSong {
     #[new]
-    pub fn new(samples: u32, timestamp: u32, uri: String) -> PyResult<Self> {
+    /// `samples_used` comes last so that the positional arguments of older
+    /// versions keep their meaning. It defaults to all the 16 KHz samples of
+    /// the `samples` milliseconds.
+    pub fn new(samples: u32, timestamp: u64, uri: String, samples_used: Option<u32>) -> PyResult<Self> {
         Ok(SignatureSong {
             samples,
+            samples_used: samples_used.unwrap_or(samples.saturating_mul(16)),
             timestamp,
             uri,
         })
@@ -66,14 +119,243 @@ impl Signature {
     pub fn new(
         geolocation: Geolocation,
         signature: SignatureSong,
-        timestamp: u32,
+        timestamp: u64,
         timezone: String,
+        quality: Option<QualityReport>,
     ) -> PyResult<Self> {
         Ok(Signature {
             geolocation,
             signature,
             timestamp,
             timezone,
+            quality,
+        })
+    }
+
+    /// Serialises the request recognizing this signature, as
+    /// {"path": ..., "body": ...}. The identifiers of the tag and of the
+    /// installation are random UUIDs unless given.
+    pub fn to_request_json(
+        &self,
+        language: Option<String>,
+        country: Option<String>,
+        device: Option<String>,
+        tag_id: Option<String>,
+        installation_id: Option<String>,
+    ) -> PyResult<String> {
+        let default_locale = RequestLocale::default();
+        let locale = RequestLocale {
+            language: language.unwrap_or(default_locale.language),
+            country: country.unwrap_or(default_locale.country),
+            device: device.unwrap_or(default_locale.device),
+        };
+
+        let tag_id = tag_id.unwrap_or_else(random_uuid);
+        let installation_id = installation_id.unwrap_or_else(random_uuid);
+        for part in [&locale.language, &locale.country, &locale.device, &tag_id, &installation_id] {
+            if !is_valid_request_id(part) {
+                return Err(PyValueError::new_err(format!(
+                    "{:?} cannot be put in a request path, only letters, digits and dashes can",
+                    part
+                )));
+            }
+        }
+
+        let request = RecognitionRequest::new(
+            &convert_signature_from_py(self),
+            &locale,
+            &tag_id,
+            &installation_id,
+        );
+        serde_json::to_string(&request).map_err(|e| PyValueError::new_err(e.to_string()))
+    }
+}
+
+#[pymethods]
+impl QualityReport {
+    #[new]
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        duration_seconds: f32,
+        peaks_per_second: HashMap<String, f32>,
+        rms_dbfs: f32,
+        peak_dbfs: f32,
+        clipped_ratio: f32,
+        silence_ratio: f32,
+        effective_bandwidth_hz: f32,
+        likely_unrecognizable: bool,
+        reasons: Vec<String>,
+    ) -> PyResult<Self> {
+        Ok(QualityReport {
+            duration_seconds,
+            peaks_per_second,
+            rms_dbfs,
+            peak_dbfs,
+            clipped_ratio,
+            silence_ratio,
+            effective_bandwidth_hz,
+            likely_unrecognizable,
+            reasons,
+        })
+    }
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+#[pyclass]
+pub(crate) struct IndexMatch {
+    #[pyo3(get)]
+    pub(crate) track_id: String,
+    #[pyo3(get)]
+    pub(crate) offset_seconds: f32,
+    #[pyo3(get)]
+    pub(crate) votes: u32,
+}
+
+#[pymethods]
+impl IndexMatch {
+    #[new]
+    pub fn new(track_id: String, offset_seconds: f32, votes: u32) -> PyResult<Self> {
+        Ok(IndexMatch {
+            track_id,
+            offset_seconds,
+            votes,
+        })
+    }
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+#[pyclass]
+pub(crate) struct DuplicateGroup {
+    #[pyo3(get)]
+    pub(crate) paths: Vec<String>,
+    #[pyo3(get)]
+    pub(crate) similarity: f32,
+}
+
+#[pymethods]
+impl DuplicateGroup {
+    #[new]
+    pub fn new(paths: Vec<String>, similarity: f32) -> PyResult<Self> {
+        Ok(DuplicateGroup { paths, similarity })
+    }
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+#[pyclass]
+pub(crate) struct DuplicateReport {
+    #[pyo3(get)]
+    pub(crate) groups: Vec<DuplicateGroup>,
+    /// Error message by path, for the files which could not be fingerprinted.
+    #[pyo3(get)]
+    pub(crate) failed: HashMap<String, String>,
+}
+
+#[pymethods]
+impl DuplicateReport {
+    #[new]
+    pub fn new(groups: Vec<DuplicateGroup>, failed: HashMap<String, String>) -> PyResult<Self> {
+        Ok(DuplicateReport { groups, failed })
+    }
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+#[pyclass]
+pub(crate) struct Alignment {
+    #[pyo3(get)]
+    pub(crate) offset_ms: f64,
+    #[pyo3(get)]
+    pub(crate) confidence: f32,
+    #[pyo3(get)]
+    pub(crate) votes: u32,
+    #[pyo3(get)]
+    pub(crate) drift_ppm: Option<f64>,
+}
+
+#[pymethods]
+impl Alignment {
+    #[new]
+    pub fn new(offset_ms: f64, confidence: f32, votes: u32, drift_ppm: Option<f64>) -> PyResult<Self> {
+        Ok(Alignment {
+            offset_ms,
+            confidence,
+            votes,
+            drift_ppm,
+        })
+    }
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+#[pyclass]
+pub(crate) struct MediaInfo {
+    #[pyo3(get)]
+    pub(crate) duration_seconds: Option<f64>,
+    #[pyo3(get)]
+    pub(crate) container: Option<String>,
+    #[pyo3(get)]
+    pub(crate) codec: Option<String>,
+    #[pyo3(get)]
+    pub(crate) sample_rate: Option<u32>,
+    #[pyo3(get)]
+    pub(crate) channels: Option<u16>,
+    #[pyo3(get)]
+    pub(crate) bitrate: Option<u64>,
+    #[pyo3(get)]
+    pub(crate) tags: HashMap<String, String>,
+}
+
+#[pymethods]
+impl MediaInfo {
+    #[new]
+    pub fn new(
+        duration_seconds: Option<f64>,
+        container: Option<String>,
+        codec: Option<String>,
+        sample_rate: Option<u32>,
+        channels: Option<u16>,
+        bitrate: Option<u64>,
+        tags: Option<HashMap<String, String>>,
+    ) -> PyResult<Self> {
+        Ok(MediaInfo {
+            duration_seconds,
+            container,
+            codec,
+            sample_rate,
+            channels,
+            bitrate,
+            tags: tags.unwrap_or_default(),
+        })
+    }
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+#[pyclass]
+pub(crate) struct StreamSignature {
+    #[pyo3(get)]
+    pub(crate) stream_index: u32,
+    /// Missing when the stream could not be fingerprinted.
+    #[pyo3(get)]
+    pub(crate) signature: Option<Signature>,
+    #[pyo3(get)]
+    pub(crate) language: Option<String>,
+    /// Why the stream could not be fingerprinted.
+    #[pyo3(get)]
+    pub(crate) error: Option<String>,
+}
+
+#[pymethods]
+impl StreamSignature {
+    #[new]
+    pub fn new(
+        stream_index: u32,
+        signature: Option<Signature>,
+        language: Option<String>,
+        error: Option<String>,
+    ) -> PyResult<Self> {
+        Ok(StreamSignature {
+            stream_index,
+            signature,
+            language,
+            error,
         })
     }
 }
@@ -1,47 +1,269 @@
-use crate::errors::SignatureError;
+use crate::errors::{
+    FFmpegError, FFmpegNotFoundError, FFmpegOutputTooLargeError, FFmpegTimeoutError,
+    FFprobeNotFoundError, SignatureError, UnsupportedCodecError,
+};
 use crate::fingerprinting::communication;
-use crate::fingerprinting::communication::get_signature_json;
+use crate::fingerprinting::communication::{get_signature_json, RequestContext};
+use crate::fingerprinting::errors::FingerprintError;
+use crate::fingerprinting::pcm::{bytes_to_i16, f32_to_i16, i32_to_i16, SampleFormat};
+use crate::fingerprinting::quality;
+use crate::fingerprinting::quality::FREQUENCY_BANDS;
+use crate::fingerprinting::shared_bytes::SharedBytes;
 use crate::fingerprinting::signature_format::DecodedSignature;
-use crate::response::{Geolocation, Signature, SignatureSong};
-use pyo3::{IntoPy, Py, PyAny, PyErr, PyResult, Python};
+use crate::response::{Geolocation, QualityReport, Signature, SignatureSong};
+use pyo3::buffer::PyBuffer;
+use pyo3::types::{PyBytes, PyDict, PyList};
+use pyo3::{FromPyObject, IntoPy, Py, PyAny, PyErr, PyObject, PyResult, Python, ToPyObject};
+use std::error::Error;
 use std::future::Future;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
 use tokio::task;
 
+/// Sets its flag when dropped, which happens when the Python future is
+/// cancelled before the task completes.
+struct SetOnDrop(Arc<AtomicBool>);
+
+impl Drop for SetOnDrop {
+    fn drop(&mut self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+}
+
+/// Runs `future` on a blocking thread and returns it as a Python awaitable.
+/// `cancelled` is set when the Python future is cancelled, so that the task
+/// can stop its work early.
 pub fn get_python_future<'py, T>(
     py: Python<'py>,
+    cancelled: Arc<AtomicBool>,
     future: impl Future<Output=PyResult<T>> + Send + 'static,
 ) -> PyResult<&'py PyAny>
     where
         T: Send + 'static,
         T: IntoPy<Py<PyAny>>,
 {
-    return pyo3_asyncio::tokio::future_into_py(py, async move {
+    pyo3_asyncio::tokio::future_into_py(py, async move {
+        let _cancel_on_drop = SetOnDrop(cancelled);
+        // The task only fails to join when it panicked or the runtime shut down
         task::spawn_blocking(move || futures::executor::block_on(future))
             .await
-            .unwrap()
-    });
+            .unwrap_or_else(|error| {
+                Err(PyErr::new::<SignatureError, _>(format!(
+                    "The task failed: {}",
+                    error
+                )))
+            })
+    })
+}
+
+/// Converts an error to the Python exception matching its kind.
+pub fn convert_error_to_py(error: &(dyn Error + 'static), message: String) -> PyErr {
+    match error.downcast_ref::<FingerprintError>() {
+        Some(FingerprintError::FFMpegNotFound) => PyErr::new::<FFmpegNotFoundError, _>(message),
+        Some(FingerprintError::FFProbeNotFound) => PyErr::new::<FFprobeNotFoundError, _>(message),
+        Some(FingerprintError::UnsupportedCodec(_)) => {
+            PyErr::new::<UnsupportedCodecError, _>(message)
+        }
+        Some(FingerprintError::FFMpegFailed {
+            command_line,
+            exit_code,
+            stderr,
+        }) => PyErr::new::<FFmpegError, _>((
+            message,
+            command_line.clone(),
+            stderr.clone(),
+            *exit_code,
+        )),
+        Some(FingerprintError::FFMpegTimedOut { .. } | FingerprintError::FFMpegStalled { .. }) => {
+            PyErr::new::<FFmpegTimeoutError, _>(message)
+        }
+        Some(FingerprintError::FFMpegOutputTooLarge { .. }) => {
+            PyErr::new::<FFmpegOutputTooLargeError, _>(message)
+        }
+        _ => PyErr::new::<SignatureError, _>(SignatureError::new(message)),
+    }
 }
 
 pub fn convert_signature_to_py(signature: communication::Signature) -> PyResult<Signature> {
     Signature::new(
-        Geolocation::new(
-            signature.geolocation.altitude,
-            signature.geolocation.latitude,
-            signature.geolocation.longitude,
-        )?,
+        convert_geolocation_to_py(&signature.geolocation)?,
         SignatureSong::new(
             signature.signature.samples,
             signature.signature.timestamp,
             signature.signature.uri,
+            Some(signature.signature.samples_used),
         )?,
         signature.timestamp,
         signature.timezone,
+        signature.quality.map(convert_quality_report_to_py),
     )
 }
 
-pub fn unwrap_decoded_signature(data: DecodedSignature) -> Result<communication::Signature, PyErr> {
-    get_signature_json(&data).map_err(|e| {
+/// Converts a signature back to the model requests are built from. The
+/// quality report is not sent, and is dropped.
+pub fn convert_signature_from_py(signature: &Signature) -> communication::Signature {
+    communication::Signature {
+        geolocation: convert_geolocation_from_py(&signature.geolocation),
+        signature: communication::SignatureSong {
+            samples: signature.signature.samples,
+            samples_used: signature.signature.samples_used,
+            timestamp: signature.signature.timestamp,
+            uri: signature.signature.uri.clone(),
+        },
+        timestamp: signature.timestamp,
+        timezone: signature.timezone.clone(),
+        quality: None,
+    }
+}
+
+pub fn convert_geolocation_to_py(geolocation: &communication::GeolocationResponse) -> PyResult<Geolocation> {
+    Geolocation::new(geolocation.altitude, geolocation.latitude, geolocation.longitude)
+}
+
+pub fn convert_geolocation_from_py(geolocation: &Geolocation) -> communication::GeolocationResponse {
+    communication::GeolocationResponse {
+        altitude: geolocation.altitude,
+        latitude: geolocation.latitude,
+        longitude: geolocation.longitude,
+    }
+}
+
+pub fn convert_quality_report_to_py(report: quality::QualityReport) -> QualityReport {
+    QualityReport {
+        duration_seconds: report.duration_seconds,
+        peaks_per_second: FREQUENCY_BANDS
+            .iter()
+            .zip(report.peaks_per_second)
+            .map(|(frequency_band, peaks_per_second)| {
+                let band_name = format!("{:?}", frequency_band);
+                (band_name.trim_start_matches('_').to_string(), peaks_per_second)
+            })
+            .collect(),
+        rms_dbfs: report.rms_dbfs,
+        peak_dbfs: report.peak_dbfs,
+        clipped_ratio: report.clipped_ratio,
+        silence_ratio: report.silence_ratio,
+        effective_bandwidth_hz: report.effective_bandwidth_hz,
+        likely_unrecognizable: report.likely_unrecognizable,
+        reasons: report.reasons,
+    }
+}
+
+/// Converts JSON to the matching Python objects: dicts, lists, strings,
+/// numbers, booleans and None.
+pub fn convert_json_to_py(py: Python, value: &serde_json::Value) -> PyObject {
+    match value {
+        serde_json::Value::Null => py.None(),
+        serde_json::Value::Bool(value) => value.to_object(py),
+        serde_json::Value::Number(number) => match (number.as_i64(), number.as_u64()) {
+            (Some(value), _) => value.to_object(py),
+            (_, Some(value)) => value.to_object(py),
+            _ => number.as_f64().to_object(py),
+        },
+        serde_json::Value::String(value) => value.to_object(py),
+        serde_json::Value::Array(values) => PyList::new(
+            py,
+            values.iter().map(|value| convert_json_to_py(py, value)),
+        )
+        .to_object(py),
+        serde_json::Value::Object(fields) => convert_json_object_to_py(py, fields),
+    }
+}
+
+pub fn convert_json_object_to_py(
+    py: Python,
+    fields: &serde_json::Map<String, serde_json::Value>,
+) -> PyObject {
+    let dict = PyDict::new(py);
+    for (key, value) in fields {
+        // Setting an item with a string key cannot fail
+        dict.set_item(key, convert_json_to_py(py, value)).unwrap();
+    }
+    dict.to_object(py)
+}
+
+pub fn unwrap_decoded_signature(
+    data: DecodedSignature,
+    context: &RequestContext,
+) -> Result<communication::Signature, PyErr> {
+    get_signature_json(&data, context).map_err(|e| {
         let error_message = format!("{}", e);
         PyErr::new::<SignatureError, _>(SignatureError::new(error_message))
     })
 }
+
+/// Reads PCM samples from an object supporting the buffer protocol, either
+/// typed with the given sample format (`array.array`, NumPy arrays) or made
+/// of raw bytes in native byte order, and converts them to 16-bit.
+pub fn read_pcm_buffer(py: Python, samples: &PyAny, format: SampleFormat) -> PyResult<Vec<i16>> {
+    let typed_samples = match format {
+        SampleFormat::I16 => PyBuffer::<i16>::get(samples).and_then(|buffer| buffer.to_vec(py)),
+        SampleFormat::I32 => PyBuffer::<i32>::get(samples)
+            .and_then(|buffer| buffer.to_vec(py))
+            .map(|samples| samples.into_iter().map(i32_to_i16).collect()),
+        SampleFormat::F32 => PyBuffer::<f32>::get(samples)
+            .and_then(|buffer| buffer.to_vec(py))
+            .map(|samples| samples.into_iter().map(f32_to_i16).collect()),
+    };
+
+    match typed_samples {
+        Ok(samples) => Ok(samples),
+        Err(typed_error) => match PyBuffer::<u8>::get(samples) {
+            Ok(buffer) => bytes_to_i16(&buffer.to_vec(py)?, format)
+                .map_err(|e| PyErr::new::<SignatureError, _>(e.to_string())),
+            Err(_) => Err(typed_error),
+        },
+    }
+}
+
+/// The buffer of a Python `bytes` object exported for as long as the
+/// decoders need it, during which its memory can neither move nor be
+/// resized.
+struct ExportedBuffer(PyBuffer<u8>);
+
+impl AsRef<[u8]> for ExportedBuffer {
+    fn as_ref(&self) -> &[u8] {
+        if self.0.len_bytes() == 0 {
+            return &[];
+        }
+        // Safety: only C-contiguous buffers of `bytes` objects are exported,
+        // see `BytesLike`. The export keeps the memory in place, and `bytes`
+        // are immutable, so it can be read without the GIL while Python code
+        // runs.
+        unsafe { std::slice::from_raw_parts(self.0.buf_ptr() as *const u8, self.0.len_bytes()) }
+    }
+}
+
+/// The content of a media file from any object supporting the buffer
+/// protocol (bytes, bytearray, memoryview, mmap). Only `bytes`, or
+/// contiguous memoryviews of them, are shared with the decoders without
+/// being copied. Any other buffer is copied, even when it is read-only: a
+/// read-only view of a bytearray or of an mmap can still be modified
+/// through the object it views while it is decoded.
+pub struct BytesLike(pub SharedBytes);
+
+/// Tells whether the memory of a buffer belongs to a `bytes` object.
+/// Subclasses are not trusted, as they can export another buffer.
+fn is_immutable_bytes(object: &PyAny) -> bool {
+    if object.is_exact_instance_of::<PyBytes>() {
+        return true;
+    }
+    // A memoryview exposes the object it views, the innermost one when nested
+    let is_memoryview = unsafe { pyo3::ffi::PyMemoryView_Check(object.as_ptr()) != 0 };
+    is_memoryview
+        && object
+            .getattr("obj")
+            .is_ok_and(|viewed| viewed.is_exact_instance_of::<PyBytes>())
+}
+
+impl<'source> FromPyObject<'source> for BytesLike {
+    fn extract(object: &'source PyAny) -> PyResult<Self> {
+        let buffer = PyBuffer::<u8>::get(object)?;
+
+        if is_immutable_bytes(object) && buffer.is_c_contiguous() {
+            Ok(BytesLike(SharedBytes::new(ExportedBuffer(buffer))))
+        } else {
+            Ok(BytesLike(buffer.to_vec(object.py())?.into()))
+        }
+    }
+}